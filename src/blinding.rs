@@ -0,0 +1,302 @@
+//! Blinding of pset outputs with secp256k1_zkp
+//!
+//! Outputs with a blinding key get Pedersen commitments for the asset and the
+//! value, a rangeproof and a surjection proof, as done by `blindpsbt`. The
+//! secrets of the confidential inputs are supplied by the user, so outputs of
+//! assets without a wallet input, like the claimbene payout, can be blinded.
+use bitcoin::hashes::{sha256d, Hash};
+use elements::pset::{self, PartiallySignedTransaction as Pset};
+use elements::secp256k1_zkp::{
+    self, compute_adaptive_blinding_factor, Generator, PedersenCommitment, PublicKey, RangeProof,
+    Secp256k1, SecretKey, SurjectionProof, Tweak, ZERO_TWEAK,
+};
+use elements::{confidential, AssetId, OutPoint, TxOut, TxOutWitness};
+
+use contract::Error;
+use funding::{issued_amount, BlindingFactor, Utxo};
+
+/// Minimum number of bits of the value proven by the rangeproofs, as used by the Elements wallet
+const RANGEPROOF_MIN_BITS: u8 = 52;
+
+// The unblinded asset and value of an input or output with the blinding factors
+struct Secrets {
+    asset: AssetId,
+    value: u64,
+    abf: Tweak,
+    vbf: Tweak,
+}
+
+impl Secrets {
+    fn explicit(asset: AssetId, value: u64) -> Secrets {
+        Secrets {
+            asset,
+            value,
+            abf: ZERO_TWEAK,
+            vbf: ZERO_TWEAK,
+        }
+    }
+
+    // The entry for computing the balancing value blinding factor
+    fn blinding_entry(&self) -> (u64, Tweak, Tweak) {
+        (self.value, self.abf, self.vbf)
+    }
+}
+
+fn random_tweak<R: secp256k1_zkp::rand::Rng>(rng: &mut R) -> Tweak {
+    let sk = SecretKey::new(rng);
+    Tweak::from_slice(&sk[..]).expect("secret key is a valid tweak")
+}
+
+// The secrets of the input spending `prevout`. Explicit inputs need no secrets.
+fn input_secrets(
+    prevout: OutPoint,
+    witness_utxo: Option<&TxOut>,
+    secrets: &[Utxo],
+) -> Result<Secrets, Error> {
+    if let Some(utxo) = secrets.iter().find(|u| u.outpoint() == prevout) {
+        let txout = utxo.txout()?;
+        if let Some(witness_utxo) = witness_utxo {
+            if witness_utxo.asset != txout.asset || witness_utxo.value != txout.value {
+                return Err(Error::InvalidUtxo(
+                    prevout,
+                    "secrets do not match the witness utxo",
+                ));
+            }
+        }
+        let mut input = Secrets::explicit(utxo.asset, utxo.value);
+        let tweak = |bf: BlindingFactor| {
+            Tweak::from_slice(bf.as_bytes())
+                .map_err(|_| Error::InvalidUtxo(prevout, "invalid blinding factor"))
+        };
+        if let Some(abf) = utxo.asset_blinder {
+            input.abf = tweak(abf)?;
+        }
+        if let Some(vbf) = utxo.value_blinder {
+            input.vbf = tweak(vbf)?;
+        }
+        return Ok(input);
+    }
+    match witness_utxo.map(|out| (out.asset, out.value)) {
+        Some((confidential::Asset::Explicit(asset), confidential::Value::Explicit(value))) => {
+            Ok(Secrets::explicit(asset, value))
+        }
+        _ => Err(Error::MissingInputSecrets(prevout)),
+    }
+}
+
+// The nonce of the rangeproof shared with the owner of `blinding_key`: the double
+// sha256 of the ecdh point as done by Elements
+fn shared_nonce(
+    secp: &Secp256k1<secp256k1_zkp::All>,
+    blinding_key: &PublicKey,
+    ephemeral_sk: &SecretKey,
+) -> SecretKey {
+    let mut point = *blinding_key;
+    point
+        .mul_assign(secp, &ephemeral_sk[..])
+        .expect("secret key is a valid tweak");
+    let nonce = sha256d::Hash::hash(&point.serialize());
+    SecretKey::from_slice(&nonce[..]).expect("hash below the curve order")
+}
+
+// Blind the explicit `txout` to `blinding_key` with the blinding factors in `secrets`.
+// The surjection proof proves that the asset is one of the `inputs`.
+fn blind_txout<R: secp256k1_zkp::rand::Rng>(
+    secp: &Secp256k1<secp256k1_zkp::All>,
+    rng: &mut R,
+    txout: &TxOut,
+    blinding_key: &PublicKey,
+    secrets: &Secrets,
+    inputs: &[Secrets],
+) -> Result<TxOut, Error> {
+    let tag = secrets.asset.into_tag();
+    let generator = Generator::new_blinded(secp, tag, secrets.abf);
+    let commitment = PedersenCommitment::new(secp, secrets.value, secrets.vbf, generator);
+
+    let ephemeral_sk = SecretKey::new(rng);
+    let nonce = shared_nonce(secp, blinding_key, &ephemeral_sk);
+    // The owner of the blinding key unblinds the asset from the rangeproof message
+    let mut message = secrets.asset.into_inner().into_inner().to_vec();
+    message.extend_from_slice(&secrets.abf[..]);
+    let rangeproof = RangeProof::new(
+        secp,
+        1,
+        commitment,
+        secrets.value,
+        secrets.vbf,
+        &message,
+        txout.script_pubkey.as_bytes(),
+        nonce,
+        0,
+        RANGEPROOF_MIN_BITS,
+        generator,
+    )?;
+
+    let domain: Vec<_> = inputs
+        .iter()
+        .map(|inp| {
+            let tag = inp.asset.into_tag();
+            (Generator::new_blinded(secp, tag, inp.abf), tag, inp.abf)
+        })
+        .collect();
+    let surjection_proof = SurjectionProof::new(secp, rng, tag, secrets.abf, &domain)?;
+
+    Ok(TxOut {
+        asset: confidential::Asset::Confidential(generator),
+        value: confidential::Value::Confidential(commitment),
+        nonce: confidential::Nonce::Confidential(PublicKey::from_secret_key(secp, &ephemeral_sk)),
+        script_pubkey: txout.script_pubkey.clone(),
+        witness: TxOutWitness {
+            surjection_proof: Some(Box::new(surjection_proof)),
+            rangeproof: Some(Box::new(rangeproof)),
+        },
+    })
+}
+
+/// Blinds all the explicit outputs of the pset with a blinding key. The asset
+/// and value blinding factors of the confidential inputs are read from
+/// `secrets` by their outpoint, explicit inputs need not be listed. The value
+/// blinding factor of the last blinded output balances the commitments of the
+/// transaction, including the explicit issuances. Returns the number of blinded outputs.
+pub fn blind_pset(pset: &mut Pset, secrets: &[Utxo]) -> Result<usize, Error> {
+    let tx = pset.extract_tx()?;
+    let mut inputs = tx
+        .input
+        .iter()
+        .zip(pset.inputs.iter())
+        .map(|(txin, inp)| input_secrets(txin.previous_output, inp.witness_utxo.as_ref(), secrets))
+        .collect::<Result<Vec<_>, _>>()?;
+    // Explicit issuances are inputs of the issued asset
+    inputs.extend(
+        tx.input
+            .iter()
+            .filter_map(issued_amount)
+            .map(|(asset, amount)| Secrets::explicit(asset, amount)),
+    );
+
+    let to_blind: Vec<usize> = pset
+        .outputs
+        .iter()
+        .enumerate()
+        .filter(|&(_, out)| out.blinding_key.is_some())
+        .filter(|&(_, out)| out.to_txout().value.is_explicit())
+        .map(|(i, _)| i)
+        .collect();
+    let last = match to_blind.last() {
+        Some(&last) => last,
+        None => return Ok(0),
+    };
+
+    let secp = Secp256k1::new();
+    let mut rng = secp256k1_zkp::rand::thread_rng();
+    let mut outputs = Vec::with_capacity(to_blind.len());
+    for &i in &to_blind {
+        let txout = pset.outputs[i].to_txout();
+        let (asset, value) = match (txout.asset, txout.value) {
+            (confidential::Asset::Explicit(asset), confidential::Value::Explicit(value)) => {
+                (asset, value)
+            }
+            _ => return Err(Error::ExpectedExplicitAsset),
+        };
+        let mut secrets = Secrets::explicit(asset, value);
+        secrets.abf = random_tweak(&mut rng);
+        secrets.vbf = if i == last {
+            let input_entries: Vec<_> = inputs.iter().map(Secrets::blinding_entry).collect();
+            let output_entries: Vec<_> = outputs
+                .iter()
+                .map(|out: &(usize, Secrets)| out.1.blinding_entry())
+                .collect();
+            compute_adaptive_blinding_factor(
+                &secp,
+                value,
+                secrets.abf,
+                &input_entries,
+                &output_entries,
+            )
+        } else {
+            random_tweak(&mut rng)
+        };
+        outputs.push((i, secrets));
+    }
+
+    for (i, secrets) in outputs {
+        let out = &mut pset.outputs[i];
+        let key = out.blinding_key.expect("Output with blinding key");
+        let blinding_key = PublicKey::from_slice(&key.to_bytes()).expect("Valid blinding key");
+        let blinded = blind_txout(
+            &secp,
+            &mut rng,
+            &out.to_txout(),
+            &blinding_key,
+            &secrets,
+            &inputs,
+        )?;
+        let blinder_index = out.blinder_index;
+        *out = pset::Output::from_txout(blinded);
+        out.blinding_key = Some(key);
+        out.blinder_index = blinder_index;
+    }
+    Ok(to_blind.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{BTC_ASSET, CTRL_PK};
+    use contract::{addr_output, txout};
+    use elements::secp256k1_zkp::verify_commitments_sum_to_equal;
+    use elements::{Address, AddressParams, Script, Transaction, Txid};
+    use std::str::FromStr;
+
+    // The value commitment of the txout, committing explicit values with a zero blinding factor
+    fn commitment(secp: &Secp256k1<secp256k1_zkp::All>, txout: &TxOut) -> PedersenCommitment {
+        match (txout.asset, txout.value) {
+            (_, confidential::Value::Confidential(comm)) => comm,
+            (confidential::Asset::Explicit(asset), confidential::Value::Explicit(value)) => {
+                let generator = Generator::new_unblinded(secp, asset.into_tag());
+                PedersenCommitment::new_unblinded(secp, value, generator)
+            }
+            _ => panic!("Unexpected txout"),
+        }
+    }
+
+    #[test]
+    fn test_blind_pset() {
+        let secp = Secp256k1::new();
+        let btc = AssetId::from_slice(&BTC_ASSET).unwrap();
+        let pk = bitcoin::PublicKey::from_str(CTRL_PK).unwrap();
+        let blinder = PublicKey::from_slice(&pk.to_bytes()).unwrap();
+        let addr = Address::p2wpkh(&pk, Some(blinder), &AddressParams::ELEMENTS);
+
+        let mut pset = Pset::from_tx(Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![],
+        });
+        let mut inp = pset::Input::from_prevout(OutPoint::new(Txid::default(), 0));
+        inp.witness_utxo = Some(txout(Script::new(), 10_000, btc));
+        pset.add_input(inp);
+        pset.add_output(addr_output(&addr, 6_000, btc));
+        pset.add_output(addr_output(&addr, 3_000, btc));
+        pset.add_output(pset::Output::from_txout(txout(Script::new(), 1_000, btc)));
+
+        assert_eq!(blind_pset(&mut pset, &[]).unwrap(), 2);
+        let outs: Vec<_> = pset.outputs.iter().map(|out| out.to_txout()).collect();
+        assert!(outs[0].value.is_confidential() && outs[1].value.is_confidential());
+        assert!(outs[0].witness.rangeproof.is_some());
+        assert!(outs[2].value.is_explicit());
+        // The blinded outputs balance the explicit input
+        let input = commitment(&secp, pset.inputs[0].witness_utxo.as_ref().unwrap());
+        let outputs: Vec<_> = outs.iter().map(|out| commitment(&secp, out)).collect();
+        assert!(verify_commitments_sum_to_equal(&secp, &[input], &outputs));
+
+        // The secrets of confidential inputs must be supplied
+        let confidential_utxo = outs[0].clone();
+        pset.inputs[0].witness_utxo = Some(confidential_utxo);
+        match blind_pset(&mut pset, &[]) {
+            Err(Error::MissingInputSecrets(..)) => {}
+            r => panic!("Expected missing input secrets, got {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Option chains: many options of the same config created and funded at once
+//!
+//! Each option of the chain needs its own opt and bene tokens, otherwise the
+//! opt tokens of one strike could exercise the option of another strike. The
+//! tokens of every option are issued by the funding transaction, which also
+//! locks the collateral of every option in its deposit covenant.
+use elements::pset::PartiallySignedTransaction as Pset;
+use elements::{Address, Transaction};
+
+use config::Config;
+use contract::{addr_output, Error};
+use funding::{self, Utxo};
+use issuance::{self, TokenRole, TokenTerms};
+use registry::{ContractEntry, OptionKind};
+
+/// The unsigned transaction issuing the tokens and funding the deposits of a chain
+#[derive(Debug, Clone)]
+pub struct ChainFunding {
+    pub pset: Pset,
+    /// The options of the chain, in the order of the terms, with the tokens
+    /// issued by the transaction
+    pub entries: Vec<ContractEntry>,
+    /// The index of the output funding the deposit of each option
+    pub deposit_vouts: Vec<u32>,
+}
+
+/// Creates the options of `kind` with the (expiry, strike) `terms` and the assets
+/// of the config. The returned transaction issues the tokens of each option to
+/// `token_addr` and sends the collateral to each deposit address, funded from the
+/// utxos at `fee_rate` sat/vB. The tokens of the i-th option are issued by the
+/// 2i-th and 2i+1-th largest btc utxos.
+pub fn fund_chain(
+    kind: OptionKind,
+    terms: &[(u32, u64)],
+    cfg: &Config,
+    utxos: &[Utxo],
+    token_addr: &Address,
+    change: &Address,
+    fee_rate: f64,
+) -> Result<ChainFunding, Error> {
+    let issuers = issuance::issuer_utxos(utxos, cfg, 2 * terms.len())?;
+    let mut inputs = vec![];
+    let mut entries = vec![];
+    for (i, &(expiry, strike)) in terms.iter().enumerate() {
+        let opt_terms = TokenTerms::new(kind, TokenRole::Opt, expiry, strike, cfg);
+        let bene_terms = TokenTerms::new(kind, TokenRole::Bene, expiry, strike, cfg);
+        let opt_prevout = issuers[2 * i].outpoint();
+        let bene_prevout = issuers[2 * i + 1].outpoint();
+        inputs.push(opt_terms.issuance_input(opt_prevout));
+        inputs.push(bene_terms.issuance_input(bene_prevout));
+
+        let mut option_cfg = cfg.clone();
+        option_cfg.opt_token = opt_terms.asset_id(opt_prevout);
+        option_cfg.bene_token = bene_terms.asset_id(bene_prevout);
+        entries.push(ContractEntry::new(kind, expiry, strike, option_cfg));
+    }
+
+    let mut partial = Pset::from_tx(Transaction {
+        version: 2,
+        lock_time: 0,
+        input: inputs,
+        output: vec![],
+    });
+    for entry in &entries {
+        let supply = entry.config.opt_token_supply;
+        partial.add_output(addr_output(token_addr, supply, entry.config.opt_token));
+        partial.add_output(addr_output(token_addr, supply, entry.config.bene_token));
+    }
+
+    // The deposits follow the token outputs, before the change and the fee
+    let mut deposit_vouts = vec![];
+    for entry in &entries {
+        let contract = entry.original_contract();
+        let deposit = contract.deposit_addr(cfg.network.address_params())?;
+        let locked = contract.locked_asset_params();
+        deposit_vouts.push(partial.outputs.len() as u32);
+        partial.add_output(addr_output(&deposit, locked.value(), locked.asset()));
+    }
+    let pset = funding::fund_pset(partial, utxos, change, cfg.btc_asset, fee_rate, 0)?;
+
+    Ok(ChainFunding {
+        pset,
+        entries,
+        deposit_vouts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{BTC_ASSET, CTRL_PK};
+    use elements::{confidential, AddressParams, AssetId};
+    use funding::issued_amount;
+    use std::str::FromStr;
+    use test_utils::{test_config, test_utxo};
+
+    #[test]
+    fn test_fund_chain() {
+        let cfg = test_config();
+        let pk = bitcoin::PublicKey::from_str(CTRL_PK).unwrap();
+        let addr = Address::p2wpkh(&pk, None, &AddressParams::ELEMENTS);
+        let btc = AssetId::from_slice(&BTC_ASSET).unwrap();
+        let utxos: Vec<_> = (0..5)
+            .map(|vout| test_utxo(vout, btc, 60_000_000))
+            .collect();
+        let terms = [
+            (1622332800, 40_000 * 100_000_000),
+            (1622332800, 50_000 * 100_000_000),
+        ];
+
+        // Each option issues its tokens from two utxos
+        match fund_chain(
+            OptionKind::Call,
+            &terms,
+            &cfg,
+            &utxos[..3],
+            &addr,
+            &addr,
+            1.0,
+        ) {
+            Err(Error::TooFewUtxos(count)) => assert_eq!(count, 4),
+            _ => panic!("Expected too few utxos"),
+        }
+
+        let funded = fund_chain(OptionKind::Call, &terms, &cfg, &utxos, &addr, &addr, 1.0)
+            .expect("Funded chain");
+        assert_eq!(funded.entries.len(), 2);
+        assert_eq!(funded.deposit_vouts, vec![4, 5]);
+        let (first, second) = (&funded.entries[0].config, &funded.entries[1].config);
+        assert_ne!(first.opt_token, second.opt_token);
+        assert_ne!(first.bene_token, second.bene_token);
+        assert_ne!(first.opt_token, first.bene_token);
+
+        let tx = funded.pset.extract_tx().unwrap();
+        assert_eq!(issued_amount(&tx.input[0]), Some((first.opt_token, 1)));
+        assert_eq!(issued_amount(&tx.input[3]), Some((second.bene_token, 1)));
+        for (entry, &vout) in funded.entries.iter().zip(&funded.deposit_vouts) {
+            let contract = entry.original_contract();
+            let deposit = contract.deposit_addr(&AddressParams::ELEMENTS).unwrap();
+            let out = &tx.output[vout as usize];
+            assert_eq!(out.script_pubkey, deposit.script_pubkey());
+            assert_eq!(out.value, confidential::Value::Explicit(100_000_000));
+        }
+    }
+}
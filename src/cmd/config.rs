@@ -1,16 +1,17 @@
-//! Create a call option on Elements
+//! Configuration of the options: the init command creating the config of the
+//! assets, the network, the control key and the oracle of the contracts
 use bitcoin;
-use elements::AssetId;
 
 use cmd;
-use serde::{Deserialize, Serialize};
-use std::{fs::File, str::FromStr};
-
-pub const CTRL_SK: &str = "cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy";
-
-pub const CTRL_PK: &str = "039b6347398505f5ec93826dc61c19f47c66c0283ee9be980e29ce325a0f4679ef";
+use options_elements::config::{self, OptionStyle, CTRL_PK, CTRL_SK};
+use options_elements::network::CustomParams;
+use options_elements::{Config, Error, Network};
+use std::path::Path;
+use std::str::FromStr;
 
 pub const BTC_ASSET: &str = "b2e15d0d7a0c94e4e2ce0fe6e8691b9e451377f6e46e8045a86f7c4b5d4f0f23";
+/// The placeholder of the tokens until they are issued by `issue`
+pub const UNISSUED_TOKEN: &str = "0000000000000000000000000000000000000000000000000000000000000000";
 
 pub fn cmd_create<'a>() -> clap::App<'a, 'a> {
     cmd::subcommand("init", "Initialize the options contract")
@@ -26,15 +27,17 @@ pub fn cmd_create<'a>() -> clap::App<'a, 'a> {
             )
             .takes_value(true)
             .required(true),
-            cmd::opt("opt-token", "The option token")
+            cmd::opt("opt-token", "The option token. Set by `issue` when omitted")
                 .takes_value(true)
-                .required(true),
+                .required(false)
+                .default_value(UNISSUED_TOKEN),
             cmd::opt(
                 "bene-token",
-                "The beneficiary token(writer token) of the option",
+                "The beneficiary token(writer token) of the option. Set by `issue` when omitted",
             )
             .takes_value(true)
-            .required(true),
+            .required(false)
+            .default_value(UNISSUED_TOKEN),
             cmd::opt(
                 "locked-asset-amount",
                 "The amount in sat of the locked asset(default 10^8)",
@@ -42,6 +45,50 @@ pub fn cmd_create<'a>() -> clap::App<'a, 'a> {
             .takes_value(true)
             .required(false)
             .default_value("100000000"),
+            cmd::opt(
+                "claim-asset-precision",
+                "The number of decimals of the claim asset(default 8)",
+            )
+            .takes_value(true)
+            .required(false)
+            .default_value("8"),
+            cmd::opt(
+                "opt-token-supply",
+                "The supply of the opt and bene tokens. Each token covers an equal share \
+                 of the locked asset(default 1)",
+            )
+            .takes_value(true)
+            .required(false)
+            .default_value("1"),
+            cmd::opt_option_style().default_value("american"),
+            cmd::opt_exercise_window().required_if("option-style", "european"),
+            cmd::opt(
+                "oracle-pk",
+                "The key of the oracle attesting the price at expiry. \
+                 Makes the options cash-settled",
+            )
+            .takes_value(true)
+            .requires("settlement-prices"),
+            cmd::opt(
+                "settlement-prices",
+                "The comma separated decimal prices of the locked asset amount in the \
+                 claim asset that cash-settled options can be settled at",
+            )
+            .takes_value(true)
+            .requires("oracle-pk"),
+            cmd::opt(
+                "private-control-key",
+                "Restrict the contracts to the holders of the private control key in \
+                 --control-keyfile. Generates the keyfile if it does not exist",
+            )
+            .takes_value(false),
+            cmd::opt(
+                "control-pk",
+                "The control key of a counterparty who signs all the contract operations",
+            )
+            .takes_value(true)
+            .conflicts_with("private-control-key"),
+            cmd::opt_control_keyfile(),
             cmd::opt("btc-asset", "The btc asset id(default regtest btc id)")
                 .takes_value(true)
                 .required(false)
@@ -53,60 +100,115 @@ pub fn cmd_create<'a>() -> clap::App<'a, 'a> {
             .takes_value(true)
             .required(false)
             .default_value("./opt_cfg.conf"),
+            cmd::opt(
+                "bech-hrp",
+                "The bech32 hrp of a custom network. Requires all custom network params",
+            )
+            .takes_value(true)
+            .requires_all(&["blech-hrp", "p2pkh-prefix", "p2sh-prefix", "blinded-prefix"])
+            .conflicts_with_all(&["elementsregtest", "liquid", "liquidtestnet"]),
+            cmd::opt("blech-hrp", "The blech32 hrp of a custom network")
+                .takes_value(true)
+                .requires("bech-hrp"),
+            cmd::opt(
+                "p2pkh-prefix",
+                "The p2pkh address prefix of a custom network",
+            )
+            .takes_value(true)
+            .requires("bech-hrp"),
+            cmd::opt("p2sh-prefix", "The p2sh address prefix of a custom network")
+                .takes_value(true)
+                .requires("bech-hrp"),
+            cmd::opt(
+                "blinded-prefix",
+                "The confidential address prefix of a custom network",
+            )
+            .takes_value(true)
+            .requires("bech-hrp"),
         ])
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Config {
-    pub lock_asset: AssetId,
-    pub claim_asset: AssetId,
-    pub opt_token: AssetId,
-    pub bene_token: AssetId,
-    pub locked_asset_amount: u64,
-    pub control_pk: bitcoin::PublicKey,
-    pub control_sk: bitcoin::PrivateKey,
-    pub btc_asset: AssetId,
+/// The network of the config from the network flags or the custom network params
+fn init_network<'a>(matches: &clap::ArgMatches<'a>) -> Result<Network, Error> {
+    if !matches.is_present("bech-hrp") {
+        return Ok(cmd::network(matches).unwrap_or_default());
+    }
+    Ok(Network::Custom(CustomParams {
+        p2pkh_prefix: cmd::parse_arg::<u8>(matches, "p2pkh-prefix")?,
+        p2sh_prefix: cmd::parse_arg::<u8>(matches, "p2sh-prefix")?,
+        blinded_prefix: cmd::parse_arg::<u8>(matches, "blinded-prefix")?,
+        bech_hrp: matches.value_of("bech-hrp").unwrap_or("").to_owned(),
+        blech_hrp: matches.value_of("blech-hrp").unwrap_or("").to_owned(),
+    }))
 }
 
-pub fn exec_init<'a>(matches: &clap::ArgMatches<'a>) {
-    let lock_asset = matches
-        .value_of("lock-asset")
-        .expect("Lock Asset missing")
-        .parse::<elements::AssetId>()
-        .expect("Invalid Lock Asset");
-
-    let claim_asset = matches
-        .value_of("claim-asset")
-        .expect("claim Asset missing")
-        .parse::<elements::AssetId>()
-        .expect("Invalid claim Asset");
-
-    let opt_token = matches
-        .value_of("opt-token")
-        .expect("Opt token missing")
-        .parse::<elements::AssetId>()
-        .expect("Invalid opt token AssetId");
+/// The control key of the config. The secret of a private control key is kept
+/// in the keyfile instead of the config, and not known at all for the key of a
+/// counterparty
+fn init_control_key<'a>(
+    matches: &clap::ArgMatches<'a>,
+) -> Result<(bitcoin::PublicKey, Option<bitcoin::PrivateKey>), Error> {
+    let secp = bitcoin::secp256k1::Secp256k1::signing_only();
+    if matches.is_present("control-pk") {
+        return Ok((cmd::parse_arg(matches, "control-pk")?, None));
+    }
+    if !matches.is_present("private-control-key") {
+        let control_sk = bitcoin::PrivateKey::from_wif(CTRL_SK).unwrap();
+        let control_pk = bitcoin::PublicKey::from_str(CTRL_PK).unwrap();
+        return Ok((control_pk, Some(control_sk)));
+    }
+    // Use the key shared by the counterparties, or generate it
+    let keyfile = matches.value_of("control-keyfile").unwrap_or("");
+    let control_sk = if Path::new(keyfile).exists() {
+        config::read_keyfile(keyfile)?
+    } else {
+        let control_sk = config::new_private_key();
+        config::write_keyfile(keyfile, &control_sk)?;
+        control_sk
+    };
+    Ok((control_sk.public_key(&secp), None))
+}
 
-    let bene_token = matches
-        .value_of("bene-token")
-        .expect("bene token missing")
-        .parse::<elements::AssetId>()
-        .expect("Invalid Bene token assetId");
+pub fn exec_init<'a>(matches: &clap::ArgMatches<'a>) -> Result<(), Error> {
+    let lock_asset = cmd::parse_arg::<elements::AssetId>(matches, "lock-asset")?;
+    let claim_asset = cmd::parse_arg::<elements::AssetId>(matches, "claim-asset")?;
+    let opt_token = cmd::parse_arg::<elements::AssetId>(matches, "opt-token")?;
+    let bene_token = cmd::parse_arg::<elements::AssetId>(matches, "bene-token")?;
+    let locked_asset_amount = cmd::parse_arg::<u64>(matches, "locked-asset-amount")?;
+    let btc_asset = cmd::parse_arg::<elements::AssetId>(matches, "btc-asset")?;
 
-    let locked_asset_amount = matches
-        .value_of("locked-asset-amount")
-        .expect("Locked asset amount error")
-        .parse::<u64>()
-        .expect("Invalid claim Asset amount");
+    let claim_asset_precision = cmd::parse_arg::<u8>(matches, "claim-asset-precision")?;
+    let oracle_pk = match matches.value_of("oracle-pk") {
+        Some(_) => Some(cmd::parse_arg::<bitcoin::PublicKey>(matches, "oracle-pk")?),
+        None => None,
+    };
+    let settlement_prices = matches
+        .value_of("settlement-prices")
+        .map(|prices| {
+            prices
+                .split(',')
+                .map(|price| {
+                    config::parse_decimal(price.trim(), claim_asset_precision)
+                        .ok_or_else(|| Error::InvalidArg("settlement-prices", prices.to_owned()))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .unwrap_or_else(|| Ok(vec![]))?;
 
-    let btc_asset = matches
-        .value_of("btc-asset")
-        .expect("btc asset missing")
-        .parse::<elements::AssetId>()
-        .expect("Invalid btc asset assetId");
+    // European options can only be exercised in a non empty window
+    let option_style = cmd::parse_arg::<OptionStyle>(matches, "option-style")?;
+    let exercise_window = match matches.value_of("exercise-window") {
+        Some(_) => cmd::parse_arg::<u32>(matches, "exercise-window")?,
+        None => 0,
+    };
+    if option_style == OptionStyle::European && exercise_window == 0 {
+        return Err(Error::InvalidExerciseWindow(exercise_window));
+    }
+    if option_style == OptionStyle::European && matches.is_present("oracle-pk") {
+        return Err(Error::EuropeanCashSettlement);
+    }
 
-    let control_sk = bitcoin::PrivateKey::from_wif(CTRL_SK).unwrap();
-    let control_pk = bitcoin::PublicKey::from_str(CTRL_PK).unwrap();
+    let (control_pk, control_sk) = init_control_key(matches)?;
 
     let cfg = Config {
         lock_asset,
@@ -117,9 +219,13 @@ pub fn exec_init<'a>(matches: &clap::ArgMatches<'a>) {
         control_pk,
         control_sk,
         btc_asset,
+        network: init_network(matches)?,
+        claim_asset_precision,
+        opt_token_supply: cmd::parse_arg::<u64>(matches, "opt-token-supply")?,
+        option_style,
+        exercise_window,
+        oracle_pk,
+        settlement_prices,
     };
-    let out_path = matches.value_of("out-file").expect("path");
-
-    let file = File::create(&out_path).expect("failed to PSBT file for writing");
-    serde_yaml::to_writer(file, &cfg).expect("Writing error")
+    cfg.to_file(matches.value_of("out-file").unwrap_or(""))
 }
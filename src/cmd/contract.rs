@@ -0,0 +1,191 @@
+//! Manage the option contracts in the registry
+use elements::OutPoint;
+use serde::Serialize;
+
+use options_elements::export::{self, ContractDescriptor};
+use options_elements::{ContractEntry, ContractState, Error};
+
+use cmd;
+
+/// The named arguments of `importaddress` watching a contract output
+#[derive(Debug, Serialize)]
+pub struct ImportAddress {
+    pub address: elements::Address,
+    pub label: String,
+    pub rescan: bool,
+}
+
+/// The arguments of `scantxoutset` finding the unspent contract outputs
+#[derive(Debug, Serialize)]
+pub struct ScanTxOutSet {
+    pub action: &'static str,
+    pub scanobjects: Vec<String>,
+}
+
+/// Output of the export command
+#[derive(Debug, Serialize)]
+pub struct ExportOutput {
+    pub id: String,
+    pub descriptors: Vec<ContractDescriptor>,
+    pub importaddress: Vec<ImportAddress>,
+    pub scantxoutset: ScanTxOutSet,
+}
+
+/// A contract of the registry along with its ID and deposit address
+#[derive(Debug, Serialize)]
+pub struct ContractInfo {
+    pub id: String,
+    pub deposit_address: elements::Address,
+    #[serde(flatten)]
+    pub entry: ContractEntry,
+}
+
+pub fn subcommand<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand_group("contract", "Manage the contracts in the registry")
+        .subcommand(cmd_list())
+        .subcommand(cmd_show())
+        .subcommand(cmd_fund())
+        .subcommand(cmd_export())
+}
+
+fn cmd_list<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand("list", "List all the contracts in the registry")
+        .args(&[cmd::opt_registry(), cmd::opt_yaml()])
+}
+
+fn cmd_show<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand("show", "Show the terms and state of a contract").args(&[
+        cmd::opt_contract().required(true),
+        cmd::opt_registry(),
+        cmd::opt_yaml(),
+    ])
+}
+
+fn cmd_fund<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand(
+        "fund",
+        "Record the outpoint locking the collateral of a contract.
+        Commands spending the contract use it when --prev-txid is not given",
+    )
+    .args(&[
+        cmd::opt_contract().required(true),
+        cmd::opt_prev_txid().required(true),
+        cmd::opt_prev_vout().required(true),
+        cmd::opt_registry(),
+        cmd::opt_yaml(),
+    ])
+}
+
+fn cmd_export<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand(
+        "export",
+        "Export the checksummed descriptors of the contract outputs along with \
+         importaddress and scantxoutset arguments for watch-only elementsd wallets",
+    )
+    .args(&[
+        cmd::opt_contract().required(true),
+        cmd::opt_registry(),
+        cmd::opt_yaml(),
+    ])
+}
+
+pub fn execute<'a>(matches: &clap::ArgMatches<'a>) -> Result<(), Error> {
+    match matches.subcommand() {
+        ("list", Some(ref m)) => exec_list(m),
+        ("show", Some(ref m)) => exec_show(m),
+        ("fund", Some(ref m)) => exec_fund(m),
+        ("export", Some(ref m)) => exec_export(m),
+        (_, _) => unreachable!("clap prints help"),
+    }
+}
+
+fn contract_info(id: &str, entry: &ContractEntry) -> Result<ContractInfo, Error> {
+    Ok(ContractInfo {
+        id: id.to_owned(),
+        deposit_address: entry
+            .contract()?
+            .deposit_addr(entry.config.network.address_params())?,
+        entry: entry.clone(),
+    })
+}
+
+fn exec_list<'a>(matches: &clap::ArgMatches<'a>) -> Result<(), Error> {
+    let registry = cmd::registry(matches)?;
+    let contracts = registry
+        .iter()
+        .map(|(id, entry)| contract_info(id, entry))
+        .collect::<Result<Vec<_>, _>>()?;
+    cmd::print_output(matches, &contracts);
+    Ok(())
+}
+
+fn exec_show<'a>(matches: &clap::ArgMatches<'a>) -> Result<(), Error> {
+    let id = matches.value_of("contract").unwrap_or("");
+    let registry = cmd::registry(matches)?;
+    cmd::print_output(matches, &contract_info(id, registry.get(id)?)?);
+    Ok(())
+}
+
+fn exec_fund<'a>(matches: &clap::ArgMatches<'a>) -> Result<(), Error> {
+    let id = matches.value_of("contract").unwrap_or("");
+    let txid = cmd::parse_arg::<elements::Txid>(matches, "prev-txid")?;
+    let vout = cmd::parse_arg::<u32>(matches, "prev-vout")?;
+
+    let mut registry = cmd::registry(matches)?;
+    {
+        let entry = registry.get_mut(id)?;
+        entry.deposit = Some(OutPoint::new(txid, vout));
+        entry.state = ContractState::Funded;
+    }
+    cmd::save_registry(matches, &registry)?;
+    cmd::print_output(matches, &contract_info(id, registry.get(id)?)?);
+    Ok(())
+}
+
+fn exec_export<'a>(matches: &clap::ArgMatches<'a>) -> Result<(), Error> {
+    let id = matches.value_of("contract").unwrap_or("");
+    let registry = cmd::registry(matches)?;
+    let entry = registry.get(id)?;
+    let params = entry.config.network.address_params();
+    let descriptors = export::contract_descriptors(&entry.original_contract(), params)?;
+
+    let importaddress = descriptors
+        .iter()
+        .map(|desc| ImportAddress {
+            address: desc.address.clone(),
+            label: match desc.remaining {
+                Some(remaining) => {
+                    format!("{}-{}-{}-{}", id, role_label(desc), desc.units, remaining)
+                }
+                None => format!("{}-{}-{}", id, role_label(desc), desc.units),
+            },
+            rescan: false,
+        })
+        .collect();
+    let scantxoutset = ScanTxOutSet {
+        action: "start",
+        scanobjects: descriptors
+            .iter()
+            .map(ContractDescriptor::scan_object)
+            .collect(),
+    };
+    cmd::print_output(
+        matches,
+        &ExportOutput {
+            id: id.to_owned(),
+            descriptors,
+            importaddress,
+            scantxoutset,
+        },
+    );
+    Ok(())
+}
+
+// The role of the descriptor in the importaddress labels
+fn role_label(desc: &ContractDescriptor) -> &'static str {
+    match desc.role {
+        export::DescriptorRole::Deposit => "deposit",
+        export::DescriptorRole::Successor => "successor",
+        export::DescriptorRole::ExercisePayout => "payout",
+    }
+}
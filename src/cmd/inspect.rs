@@ -0,0 +1,73 @@
+//! Decode an option covenant back into the option terms
+use elements::{Address, Script};
+use hex;
+use serde::Serialize;
+use std::str::FromStr;
+
+use options_elements::inspect::{self, CovenantTerms};
+use options_elements::Error;
+
+use cmd;
+
+/// Output of the inspect command
+#[derive(Debug, Serialize)]
+pub struct InspectOutput {
+    /// The ID of the contract, as in the registry
+    pub contract_id: String,
+    /// The deposit address of the covenant on the selected network
+    pub deposit_address: Address,
+    #[serde(flatten)]
+    pub terms: CovenantTerms,
+}
+
+pub fn cmd_inspect<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand(
+        "inspect",
+        "Decode the terms of an option from its elcovwsh descriptor or witness script",
+    )
+    .args(&cmd::opts_networks())
+    .args(&[
+        cmd::opt("descriptor", "The elcovwsh descriptor of the covenant")
+            .takes_value(true)
+            .required_unless("script")
+            .conflicts_with("script"),
+        cmd::opt("script", "The hex witness script of the covenant").takes_value(true),
+        cmd::opt(
+            "address",
+            "The deposit address to check the covenant against",
+        )
+        .takes_value(true),
+        cmd::opt_yaml(),
+    ])
+}
+
+pub fn exec_inspect<'a>(matches: &clap::ArgMatches<'a>) -> Result<(), Error> {
+    let terms = if let Some(desc_str) = matches.value_of("descriptor") {
+        inspect::decode_descriptor(desc_str)?
+    } else {
+        let script_hex = matches.value_of("script").unwrap_or("");
+        let script = hex::decode(script_hex)
+            .map(Script::from)
+            .map_err(|_| Error::InvalidArg("script", script_hex.to_owned()))?;
+        inspect::decode_script(&script)?
+    };
+
+    let network = cmd::network(matches).unwrap_or_default();
+    let deposit_address = terms.deposit_addr(network.address_params())?;
+    if let Some(addr_str) = matches.value_of("address") {
+        let addr = Address::from_str(addr_str)
+            .map_err(|_| Error::InvalidArg("address", addr_str.to_owned()))?;
+        if addr.script_pubkey() != deposit_address.script_pubkey() {
+            return Err(Error::InvalidArg("address", addr_str.to_owned()));
+        }
+    }
+    cmd::print_output(
+        matches,
+        &InspectOutput {
+            contract_id: terms.contract_id()?.to_string(),
+            deposit_address,
+            terms,
+        },
+    );
+    Ok(())
+}
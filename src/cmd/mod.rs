@@ -1,38 +1,35 @@
-use elements::AddressParams;
-use serde::{Deserialize, Serialize};
+use chrono::{Date, DateTime, NaiveDate, NaiveTime, Utc};
+use elements::pset::PartiallySignedTransaction as Pset;
+use elements::{Address, AddressParams, OutPoint, Txid};
+use options_elements::config::{read_keyfile, OptionStyle};
+use options_elements::contract::{check_wallet_blinding, LOCKTIME_THRESHOLD};
+use options_elements::{blinding, funding, rpc, Config, ContractEntry, Error, OptionContract};
+use options_elements::{ExercisePayout, Network, OptionKind, Registry, SpendType, Utxo};
+use std::path::Path;
+use std::str::FromStr;
 
 pub mod call;
 pub mod config;
-pub use self::call::{OptAssetParams, OptionContract, BTC_ASSET, CTRL_PK, CTRL_SK};
-pub use self::config::Config;
-
-/// Known Elements networks.
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
-#[serde(rename_all = "lowercase")]
-pub enum Network {
-    ElementsRegtest,
-    Liquid,
-}
-
-impl Network {
-    pub fn from_params(params: &'static AddressParams) -> Option<Network> {
-        match params {
-            &AddressParams::ELEMENTS => Some(Network::ElementsRegtest),
-            &AddressParams::LIQUID => Some(Network::Liquid),
-            _ => None,
-        }
-    }
+pub mod contract;
+pub mod inspect;
+pub mod offer;
+pub mod oracle;
+pub mod put;
+pub mod tokens;
 
-    pub fn address_params(self) -> &'static AddressParams {
-        match self {
-            Network::ElementsRegtest => &AddressParams::ELEMENTS,
-            Network::Liquid => &AddressParams::LIQUID,
-        }
-    }
-}
 /// Build a list of all built-in subcommands.
 pub fn subcommands<'a>() -> Vec<clap::App<'a, 'a>> {
-    vec![call::subcommand(), config::cmd_create()]
+    vec![
+        call::subcommand(),
+        put::subcommand(),
+        contract::subcommand(),
+        config::cmd_create(),
+        tokens::cmd_issue(),
+        tokens::cmd_verify_tokens(),
+        inspect::cmd_inspect(),
+        oracle::subcommand(),
+        offer::subcommand(),
+    ]
 }
 
 /// Construct a new command option.
@@ -77,19 +74,545 @@ pub fn opts_networks<'a>() -> Vec<clap::Arg<'a, 'a>> {
             .help("run in liquid mode")
             .takes_value(false)
             .required(false),
+        clap::Arg::with_name("liquidtestnet")
+            .long("liquidtestnet")
+            .help("run in liquid testnet mode")
+            .takes_value(false)
+            .required(false),
     ]
 }
 
-pub fn network<'a>(matches: &clap::ArgMatches<'a>) -> Network {
+/// The network selected by the network flags, if any
+pub fn network<'a>(matches: &clap::ArgMatches<'a>) -> Option<Network> {
     if matches.is_present("elementsregtest") {
-        Network::ElementsRegtest
+        Some(Network::ElementsRegtest)
     } else if matches.is_present("liquid") {
-        Network::Liquid
+        Some(Network::Liquid)
+    } else if matches.is_present("liquidtestnet") {
+        Some(Network::LiquidTestnet)
     } else {
-        Network::ElementsRegtest
+        None
     }
 }
 
+/// The address params of the network in the config. A network flag that
+/// does not match the config is rejected.
+pub fn address_params<'a>(
+    matches: &clap::ArgMatches<'a>,
+    cfg: &Config,
+) -> Result<&'static AddressParams, Error> {
+    match network(matches) {
+        Some(ref network) if *network != cfg.network => {
+            Err(Error::InvalidArg("network", network.to_string()))
+        }
+        _ => Ok(cfg.network.address_params()),
+    }
+}
+
+/// Parse the `addr` argument as an address of the network in the config
+pub fn parse_addr<'a>(matches: &clap::ArgMatches<'a>, cfg: &Config) -> Result<Address, Error> {
+    parse_addr_arg(matches, cfg, "addr")
+}
+
+/// Parse the argument `name` as an address of the network in the config
+pub fn parse_addr_arg<'a>(
+    matches: &clap::ArgMatches<'a>,
+    cfg: &Config,
+    name: &'static str,
+) -> Result<Address, Error> {
+    let params = address_params(matches, cfg)?;
+    let value = matches.value_of(name).unwrap_or("");
+    Address::parse_with_params(value, params)
+        .map_err(|_| Error::WrongNetwork(value.to_owned(), cfg.network.clone()))
+}
+
+/// Arguments that identify an option contract: expiry, strike and the config file
+/// holding the assets and tokens, or the ID of a contract in the registry.
+pub fn opts_contract<'a>() -> Vec<clap::Arg<'a, 'a>> {
+    vec![
+        opt_expiry().required_unless("contract"),
+        opt_strike().required_unless("contract"),
+        opt_cfg_file(),
+        opt_contract().required(false).conflicts_with_all(&[
+            "expiry",
+            "strike",
+            "option-style",
+            "exercise-window",
+        ]),
+        opt_option_style(),
+        opt_exercise_window(),
+        opt(
+            "units",
+            "The number of option tokens to exercise, or claim the strike of with \
+             bene tokens. Default all the remaining tokens, or the exercised tokens of \
+             the payout recorded at the prevout for claimbene",
+        )
+        .takes_value(true)
+        .required(false),
+        opt(
+            "price",
+            "The settlement price of a cash-settled option attested by the oracle, \
+             in units of the claim asset. A price off the settlement grid settles at \
+             the nearest grid price",
+        )
+        .takes_value(true),
+        opt(
+            "oracle-sig",
+            "The DER hex oracle signature of the settlement price",
+        )
+        .takes_value(true)
+        .requires("price"),
+        opt_control_keyfile(),
+        opt_registry(),
+    ]
+}
+
+pub fn opt_control_keyfile<'a>() -> clap::Arg<'a, 'a> {
+    opt(
+        "control-keyfile",
+        "Path of the keyfile with the private control key of permissioned contracts. \
+         The default keyfile is only used if the config has no control key and the \
+         keyfile holds its private key. Default=./opt_control.key",
+    )
+    .takes_value(true)
+    .required(false)
+    .default_value("./opt_control.key")
+}
+
+pub fn opt_expiry<'a>() -> clap::Arg<'a, 'a> {
+    opt(
+        "expiry",
+        "The expiry of the option as a block height, an RFC 3339 timestamp \
+         or a date(YYYY-MM-DD, midnight UTC)",
+    )
+    .takes_value(true)
+}
+
+pub fn opt_strike<'a>() -> clap::Arg<'a, 'a> {
+    opt(
+        "strike",
+        "The strike price of the option in units of the claim asset. \
+         Must be representable with the claim asset precision",
+    )
+    .takes_value(true)
+}
+
+pub fn opt_cfg_file<'a>() -> clap::Arg<'a, 'a> {
+    opt(
+        "cfg-file",
+        "Path for reading config file. Default=./opt_cfg.conf",
+    )
+    .takes_value(true)
+    .required(false)
+    .default_value("./opt_cfg.conf")
+}
+
+pub fn opt_option_style<'a>() -> clap::Arg<'a, 'a> {
+    opt(
+        "option-style",
+        "The exercise style of the option, american or european. European options can only \
+         be exercised from the start of the exercise window, but the covenant cannot close the \
+         window at expiry: they stay exercisable until the expiry is claimed. Defaults to the \
+         config",
+    )
+    .takes_value(true)
+    .possible_values(&["american", "european"])
+}
+
+pub fn opt_exercise_window<'a>() -> clap::Arg<'a, 'a> {
+    opt(
+        "exercise-window",
+        "The exercise window of european options, opening before expiry, in blocks for \
+         height expiries and seconds otherwise. Defaults to the config",
+    )
+    .takes_value(true)
+}
+
+pub fn opt_contract<'a>() -> clap::Arg<'a, 'a> {
+    opt("contract", "The ID of a contract in the registry").takes_value(true)
+}
+
+pub fn opt_registry<'a>() -> clap::Arg<'a, 'a> {
+    opt(
+        "registry",
+        "Path of the contract registry file. Default=./opt_registry.yaml",
+    )
+    .takes_value(true)
+    .required(false)
+    .default_value("./opt_registry.yaml")
+}
+
+/// Read the contract registry at the `registry` argument
+pub fn registry<'a>(matches: &clap::ArgMatches<'a>) -> Result<Registry, Error> {
+    Registry::from_file(matches.value_of("registry").unwrap_or(""))
+}
+
+/// Write the contract registry to the `registry` argument
+pub fn save_registry<'a>(matches: &clap::ArgMatches<'a>, registry: &Registry) -> Result<(), Error> {
+    registry.to_file(matches.value_of("registry").unwrap_or(""))
+}
+
+/// Read the contract from the registry if `contract` is set, otherwise create
+/// an unregistered entry from the expiry, strike and config file arguments.
+pub fn contract_entry<'a>(
+    matches: &clap::ArgMatches<'a>,
+    kind: OptionKind,
+) -> Result<ContractEntry, Error> {
+    if let Some(id) = matches.value_of("contract") {
+        let entry = registry(matches)?.get(id)?.clone();
+        if entry.kind != kind {
+            return Err(Error::InvalidArg("contract", id.to_owned()));
+        }
+        return Ok(entry);
+    }
+    let (expiry, strike_amt, cfg) = contract_params(matches)?;
+    Ok(ContractEntry::new(kind, expiry, strike_amt, cfg))
+}
+
+/// The contract of the entry exercising the number of tokens in `units`, if set,
+/// and settled at the attested `price` if it is cash-settled. The control key of
+/// permissioned contracts is read from `control-keyfile`, or from the default
+/// keyfile if the config has no control key. Claimbene claims the exercise
+/// payout recorded at the prevout, or the only recorded payout.
+pub fn option_contract<'a>(
+    matches: &clap::ArgMatches<'a>,
+    entry: &ContractEntry,
+    ty: SpendType,
+) -> Result<OptionContract, Error> {
+    let payout = match ty {
+        SpendType::ClaimBene => recorded_payout(matches, entry)?,
+        _ => None,
+    };
+    let mut contract = match (ty, payout) {
+        (_, Some(payout)) => entry.payout_contract(payout)?,
+        // Without a recorded payout, the payout of an exercise of the original contract
+        (SpendType::ClaimBene, None) => entry.original_contract(),
+        _ => entry.contract()?,
+    };
+    // Permissioned contracts sign with the private control key of the keyfile.
+    // A keyfile given with --control-keyfile must hold the control key, the
+    // default keyfile is only read if the config has no control key, and is
+    // ignored if it holds the key of another contract
+    let keyfile = matches.value_of("control-keyfile").unwrap_or("");
+    if matches.occurrences_of("control-keyfile") > 0 {
+        contract = contract.with_control_sk(read_keyfile(keyfile)?)?;
+    } else if entry.config.control_sk.is_none() && Path::new(keyfile).exists() {
+        if let Ok(signing) = contract.clone().with_control_sk(read_keyfile(keyfile)?) {
+            contract = signing;
+        }
+    }
+    if let Some(price) = matches.value_of("price") {
+        let oracle_sig = match matches.value_of("oracle-sig") {
+            Some(_) => Some(parse_arg::<elements::secp256k1_zkp::Signature>(
+                matches,
+                "oracle-sig",
+            )?),
+            None => None,
+        };
+        contract = contract.with_settlement_price(entry.config.price_amount(price)?, oracle_sig)?;
+    }
+    if !matches.is_present("units") {
+        return Ok(contract);
+    }
+    let units = parse_arg::<u64>(matches, "units")?;
+    if payout.is_some() && units != contract.exercise_units() {
+        return Err(Error::InvalidArg("units", units.to_string()));
+    }
+    contract.with_exercise_units(units)
+}
+
+// The exercise payout of the entry at the prevout. The prevout can be omitted
+// if the entry has a single payout.
+fn recorded_payout<'a, 'e>(
+    matches: &clap::ArgMatches<'a>,
+    entry: &'e ContractEntry,
+) -> Result<Option<&'e ExercisePayout>, Error> {
+    if !matches.is_present("prev-txid") {
+        return match entry.payouts.len() {
+            0 | 1 => Ok(entry.payouts.first()),
+            _ => Err(Error::InvalidArg("prev-txid", String::new())),
+        };
+    }
+    let outpoint = OutPoint::new(
+        parse_arg::<Txid>(matches, "prev-txid")?,
+        parse_arg::<u32>(matches, "prev-vout")?,
+    );
+    Ok(entry
+        .payouts
+        .iter()
+        .find(|payout| payout.outpoint == outpoint))
+}
+
+/// Record the spend of the registered contract with `ty` in the transaction `txid`.
+/// Does nothing if the contract is not selected by `contract`.
+pub fn update_contract_state<'a>(
+    matches: &clap::ArgMatches<'a>,
+    contract: &OptionContract,
+    ty: SpendType,
+    txid: Txid,
+) -> Result<(), Error> {
+    if let Some(id) = matches.value_of("contract") {
+        let mut registry = registry(matches)?;
+        registry.get_mut(id)?.record_spend(contract, ty, txid);
+        save_registry(matches, &registry)?;
+    }
+    Ok(())
+}
+
+/// Parse the value of the argument `name`
+pub fn parse_arg<'a, T: FromStr>(
+    matches: &clap::ArgMatches<'a>,
+    name: &'static str,
+) -> Result<T, Error> {
+    let value = matches.value_of(name).unwrap_or("");
+    value
+        .parse::<T>()
+        .map_err(|_| Error::InvalidArg(name, value.to_owned()))
+}
+
+/// Parse the expiry as the value of `after()` in the contract. The expiry can be
+/// a block height(below 500000000), an RFC 3339 timestamp or a date(YYYY-MM-DD)
+/// which expires at midnight UTC.
+pub fn parse_expiry(value: &str) -> Result<u32, Error> {
+    let invalid = || Error::InvalidArg("expiry", value.to_owned());
+    if let Ok(height) = value.parse::<u32>() {
+        return if height < LOCKTIME_THRESHOLD {
+            Ok(height)
+        } else {
+            Err(invalid())
+        };
+    }
+    let timestamp = if let Ok(date_time) = DateTime::parse_from_rfc3339(value) {
+        date_time.timestamp()
+    } else {
+        let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| invalid())?;
+        Date::<Utc>::from_utc(date, Utc)
+            .and_time(NaiveTime::from_hms(0, 0, 0))
+            .unwrap()
+            .timestamp()
+    };
+    if timestamp < LOCKTIME_THRESHOLD as i64 || timestamp > u32::max_value() as i64 {
+        return Err(invalid());
+    }
+    Ok(timestamp as u32)
+}
+
+/// Read the expiry, strike amount and the config file from the
+/// command line arguments. The option style of the config can be overridden.
+pub fn contract_params<'a>(matches: &clap::ArgMatches<'a>) -> Result<(u32, u64, Config), Error> {
+    let expiry = parse_expiry(matches.value_of("expiry").unwrap_or(""))?;
+
+    let mut cfg = Config::from_file(matches.value_of("cfg-file").unwrap_or(""))?;
+    if matches.is_present("option-style") {
+        cfg.option_style = parse_arg::<OptionStyle>(matches, "option-style")?;
+    }
+    if matches.is_present("exercise-window") {
+        cfg.exercise_window = parse_arg::<u32>(matches, "exercise-window")?;
+    }
+    let strike_amt = cfg.strike_amount(matches.value_of("strike").unwrap_or(""))?;
+    Ok((expiry, strike_amt, cfg))
+}
+
+/// Read the contract operation from the `type` argument
+pub fn spend_type<'a>(matches: &clap::ArgMatches<'a>) -> Result<SpendType, Error> {
+    matches.value_of("type").unwrap_or("").parse::<SpendType>()
+}
+
+/// Arguments for adding the contract input and output to a funded transaction.
+pub fn opts_addcontract<'a>() -> Vec<clap::Arg<'a, 'a>> {
+    vec![
+        opt_addr().required(true),
+        opt_prev_txid().required_unless("contract"),
+        opt_prev_vout().required_unless("contract"),
+        opt("funded-pset", "The funded base64 pset from elements")
+            .takes_value(true)
+            .required(true),
+        opt_type(),
+        opt(
+            "fee-rate",
+            "The fee rate in sat/vB the pset was funded at. Pays the fee for the \
+             covenant input and the contract output from the contract output or the change",
+        )
+        .takes_value(true),
+    ]
+}
+
+pub fn opt_addr<'a>() -> clap::Arg<'a, 'a> {
+    opt("addr", "The address at which to receive locked asset").takes_value(true)
+}
+
+pub fn opt_prev_txid<'a>() -> clap::Arg<'a, 'a> {
+    opt(
+        "prev-txid",
+        "The txid of contract. Defaults to the deposit of the registered contract",
+    )
+    .takes_value(true)
+}
+
+pub fn opt_prev_vout<'a>() -> clap::Arg<'a, 'a> {
+    opt("prev-vout", "The out index of contract txid").takes_value(true)
+}
+
+/// Arguments for running all the steps of a contract operation against elementsd
+pub fn opts_execute<'a>() -> Vec<clap::Arg<'a, 'a>> {
+    vec![
+        opt(
+            "execute",
+            "Fund, blind, sign, finalize and broadcast the transaction using elementsd rpc",
+        )
+        .takes_value(false)
+        .requires("addr"),
+        opt_addr(),
+        opt_prev_txid(),
+        opt_prev_vout(),
+        opt(
+            "rpc-url",
+            "The elementsd rpc url. Use /wallet/<name> path to select wallet",
+        )
+        .takes_value(true)
+        .default_value("http://127.0.0.1:18884"),
+        opt("rpc-user", "The elementsd rpc user").takes_value(true),
+        opt("rpc-pass", "The elementsd rpc password").takes_value(true),
+        opt("rpc-cookie", "Path to the elementsd .cookie file")
+            .takes_value(true)
+            .conflicts_with("rpc-user"),
+        opt(
+            "fee-rate",
+            "The fee rate in sat/vB for funding and the covenant input",
+        )
+        .takes_value(true)
+        .default_value("1"),
+    ]
+}
+
+/// Arguments for funding a contract operation from a list of utxos without elementsd.
+/// Used along with the `addr`, prevout and `fee-rate` arguments of `opts_execute`.
+pub fn opts_funding<'a>() -> Vec<clap::Arg<'a, 'a>> {
+    vec![
+        opt(
+            "utxos",
+            "Path of a JSON list of utxos(txid, vout, asset, value, asset_blinder, \
+             value_blinder, script_pubkey) to fund the transaction from. Outputs the \
+             unsigned pset including the contract input",
+        )
+        .takes_value(true)
+        .conflicts_with("execute")
+        .requires_all(&["addr", "change-addr"]),
+        opt(
+            "change-addr",
+            "The address receiving the change of the utxos",
+        )
+        .takes_value(true),
+    ]
+}
+
+/// Arguments for blinding the outputs in the tool instead of the wallet
+pub fn opts_blinding<'a>() -> Vec<clap::Arg<'a, 'a>> {
+    vec![
+        opt(
+            "blind",
+            "Blind the outputs to confidential addresses in the tool instead of the wallet. \
+             Required for claimbene to a confidential address",
+        )
+        .takes_value(false)
+        .conflicts_with("execute"),
+        opt(
+            "input-secrets",
+            "Path of a JSON list of the confidential inputs in the format of --utxos with \
+             the asset, value and blinding factors used for blinding. Explicit inputs and \
+             the inputs of --utxos need not be listed",
+        )
+        .takes_value(true)
+        .requires("blind"),
+    ]
+}
+
+/// Read the input secrets for blinding from the `input-secrets` argument, if any
+pub fn input_secrets<'a>(matches: &clap::ArgMatches<'a>) -> Result<Vec<Utxo>, Error> {
+    match matches.value_of("input-secrets") {
+        Some(path) => funding::utxos_from_file(path),
+        None => Ok(vec![]),
+    }
+}
+
+/// Blind the pset in the tool if `blind` is set. Otherwise check that the
+/// wallet can blind the outputs of the contract operation.
+pub fn blind_or_check<'a>(
+    matches: &clap::ArgMatches<'a>,
+    pset: &mut Pset,
+    ty: SpendType,
+    addr: &Address,
+    utxos: &[Utxo],
+) -> Result<(), Error> {
+    if !matches.is_present("blind") {
+        return check_wallet_blinding(ty, addr);
+    }
+    blind(matches, pset, utxos)
+}
+
+/// Blind the pset in the tool with the input secrets and the `utxos` funding it
+pub fn blind<'a>(
+    matches: &clap::ArgMatches<'a>,
+    pset: &mut Pset,
+    utxos: &[Utxo],
+) -> Result<(), Error> {
+    let mut secrets = input_secrets(matches)?;
+    secrets.extend_from_slice(utxos);
+    let n_blinded = blinding::blind_pset(pset, &secrets)?;
+    eprintln!("Blinded {} outputs", n_blinded);
+    Ok(())
+}
+
+/// Create the elementsd rpc client from the command line arguments
+pub fn rpc_client<'a>(matches: &clap::ArgMatches<'a>) -> Result<rpc::Client, Error> {
+    let url = matches.value_of("rpc-url").unwrap_or("");
+    let auth = if let Some(cookie) = matches.value_of("rpc-cookie") {
+        rpc::Auth::CookieFile(cookie.into())
+    } else if let Some(user) = matches.value_of("rpc-user") {
+        let pass = matches.value_of("rpc-pass").unwrap_or("");
+        rpc::Auth::UserPass(user.to_owned(), pass.to_owned())
+    } else {
+        rpc::Auth::None
+    };
+    Ok(rpc::Client::new(url, auth)?)
+}
+
+/// Arguments for finalizing the contract input of a signed transaction.
+pub fn opts_finalize<'a>() -> Vec<clap::Arg<'a, 'a>> {
+    vec![
+        opt("signed-pset", "The signed base64 pset from elements")
+            .takes_value(true)
+            .required(true),
+        opt_type(),
+        // The exercise payout claimed by claimbene
+        opt_prev_txid(),
+        opt_prev_vout(),
+    ]
+}
+
+/// Arguments for verifying the transaction spending the contract
+pub fn opts_verify<'a>() -> Vec<clap::Arg<'a, 'a>> {
+    vec![
+        opt("tx-hex", "The raw hex of the finalized transaction")
+            .takes_value(true)
+            .required(true),
+        opt_type(),
+        // The exercise payout claimed by claimbene
+        opt_prev_txid(),
+        opt_prev_vout(),
+    ]
+}
+
+pub fn opt_type<'a>() -> clap::Arg<'a, 'a> {
+    opt(
+        "type",
+        "The type of asset with contract operation.\
+            Use either exercise/expiry/cancel/claimbene",
+    )
+    .takes_value(true)
+    .required(true)
+}
+
 pub fn opt_yaml<'a>() -> clap::Arg<'a, 'a> {
     clap::Arg::with_name("yaml")
         .long("yaml")
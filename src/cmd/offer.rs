@@ -0,0 +1,461 @@
+//! Sell opt tokens for a premium in a single transaction signed by both sides
+use elements::encode::serialize_hex;
+use elements::pset::PartiallySignedTransaction as Pset;
+use elements::Address;
+use serde::Serialize;
+
+use options_elements::contract::{pset_from_base64, pset_to_base64};
+use options_elements::offer::{self, OfferTerms, WriteSellTerms};
+use options_elements::{funding, Config, ContractEntry, Error, OptionKind};
+
+use cmd;
+
+/// Output of the offer commands
+#[derive(Debug, Serialize)]
+pub struct OfferOutput {
+    pub terms: OfferTerms,
+    /// The base64 encoded pset
+    pub pset: String,
+    /// The transaction hex of the pset, once accepted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_hex: Option<String>,
+    pub next_step: &'static str,
+}
+
+/// Output of the write-and-sell commands
+#[derive(Debug, Serialize)]
+pub struct WriteSellOutput {
+    /// The ID of the contract in the registry
+    pub contract_id: String,
+    /// The covenant address funded by the first output
+    pub deposit_address: Address,
+    pub terms: WriteSellTerms,
+    /// The base64 encoded pset
+    pub pset: String,
+    pub next_step: &'static str,
+}
+
+pub fn subcommand<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand_group(
+        "offer",
+        "Sell opt tokens for a premium in one transaction without a trusted exchange",
+    )
+    .subcommand(cmd_sell())
+    .subcommand(cmd_accept())
+    .subcommand(cmd_write())
+    .subcommand(cmd_take())
+    .subcommand(cmd_check())
+}
+
+/// Arguments selecting the config of the option, from the registry or the config file
+fn opts_option<'a>() -> Vec<clap::Arg<'a, 'a>> {
+    vec![
+        cmd::opt_cfg_file(),
+        cmd::opt_contract().required(false),
+        cmd::opt_registry(),
+    ]
+}
+
+fn cmd_sell<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand(
+        "sell",
+        "Offer the opt tokens of an explicit utxo for a premium in the claim asset.
+        Outputs a pset to sign with SINGLE|ANYONECANPAY and hand over to the buyer.
+        Example usage: elements-cli walletprocesspsbt <pset> true SINGLE|ANYONECANPAY",
+    )
+    .args(&cmd::opts_networks())
+    .args(&opts_option())
+    .args(&[
+        cmd::opt(
+            "utxos",
+            "Path of a JSON list of utxos(txid, vout, asset, value, script_pubkey). \
+             All the tokens of the first opt token utxo are offered",
+        )
+        .takes_value(true)
+        .required(true),
+        cmd::opt(
+            "premium",
+            "The decimal premium asked for the tokens, in units of the claim asset",
+        )
+        .takes_value(true)
+        .required(true),
+        cmd::opt(
+            "premium-addr",
+            "The address receiving the premium. The premium output is explicit",
+        )
+        .takes_value(true)
+        .required(true),
+        cmd::opt_yaml(),
+    ])
+}
+
+fn cmd_accept<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand(
+        "accept",
+        "Accept an offer signed by the seller. Adds the output receiving the tokens and
+        funds the premium and the fee from the utxos. Check the terms, then sign the
+        pset with the wallet owning the utxos and broadcast it",
+    )
+    .args(&cmd::opts_networks())
+    .args(&opts_option())
+    .args(&cmd::opts_blinding())
+    .args(&[
+        cmd::opt(
+            "offer-pset",
+            "The base64 pset of the offer signed by the seller",
+        )
+        .takes_value(true)
+        .required(true),
+        cmd::opt("addr", "The address receiving the tokens")
+            .takes_value(true)
+            .required(true),
+        cmd::opt(
+            "utxos",
+            "Path of a JSON list of utxos(txid, vout, asset, value, asset_blinder, \
+             value_blinder, script_pubkey) to pay the premium and the fee from",
+        )
+        .takes_value(true)
+        .required(true),
+        cmd::opt(
+            "change-addr",
+            "The address receiving the change of the utxos",
+        )
+        .takes_value(true)
+        .required(true),
+        cmd::opt("fee-rate", "The fee rate in sat/vB of the transaction")
+            .takes_value(true)
+            .default_value("1"),
+        cmd::opt_yaml(),
+    ])
+}
+
+/// Arguments selecting the option written and sold, from the registry or the terms
+fn opts_write_sell<'a>() -> Vec<clap::Arg<'a, 'a>> {
+    vec![
+        cmd::opt_expiry().required_unless("contract"),
+        cmd::opt_strike().required_unless("contract"),
+        cmd::opt_cfg_file(),
+        cmd::opt_contract()
+            .required(false)
+            .conflicts_with_all(&["expiry", "strike"]),
+        cmd::opt_registry(),
+        cmd::opt("put", "The option is a put option instead of a call option")
+            .takes_value(false)
+            .conflicts_with("contract"),
+    ]
+}
+
+fn cmd_write<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand(
+        "write",
+        "Write an option and sell all its opt tokens in one transaction. Outputs the
+        writer leg funding the deposit and the tokens sent to the buyer, with the premium
+        output left for the buyer to fund with take. Adds the contract to the registry",
+    )
+    .args(&cmd::opts_networks())
+    .args(&opts_write_sell())
+    .args(&[
+        cmd::opt(
+            "buyer-addr",
+            "The address of the buyer receiving the opt tokens",
+        )
+        .takes_value(true)
+        .required(true),
+        cmd::opt(
+            "utxos",
+            "Path of a JSON list of utxos(txid, vout, asset, value, asset_blinder, \
+             value_blinder, script_pubkey) holding the collateral and the opt tokens",
+        )
+        .takes_value(true)
+        .required(true),
+        cmd::opt(
+            "change-addr",
+            "The address receiving the change of the utxos",
+        )
+        .takes_value(true)
+        .required(true),
+        cmd::opt(
+            "premium",
+            "The decimal premium asked for the tokens, in units of the claim asset",
+        )
+        .takes_value(true)
+        .required(true),
+        cmd::opt(
+            "premium-addr",
+            "The address receiving the premium. The premium output is explicit",
+        )
+        .takes_value(true)
+        .required(true),
+        cmd::opt("fee-rate", "The fee rate in sat/vB of the writer leg")
+            .takes_value(true)
+            .default_value("1"),
+        cmd::opt_yaml(),
+    ])
+}
+
+fn cmd_take<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand(
+        "take",
+        "Complete the writer leg of write with the buyer leg paying the premium.
+        Checks the deposit, the tokens and the premium, then funds the premium and the
+        fee from the utxos. Both sides sign the pset once checked",
+    )
+    .args(&cmd::opts_networks())
+    .args(&opts_write_sell())
+    .args(&cmd::opts_blinding())
+    .args(&[
+        cmd::opt("write-pset", "The base64 pset of the writer leg")
+            .takes_value(true)
+            .required(true),
+        cmd::opt("addr", "The address receiving the tokens")
+            .takes_value(true)
+            .required(true),
+        cmd::opt(
+            "utxos",
+            "Path of a JSON list of utxos(txid, vout, asset, value, asset_blinder, \
+             value_blinder, script_pubkey) to pay the premium and the fee from",
+        )
+        .takes_value(true)
+        .required(true),
+        cmd::opt(
+            "change-addr",
+            "The address receiving the change of the utxos",
+        )
+        .takes_value(true)
+        .required(true),
+        cmd::opt("fee-rate", "The fee rate in sat/vB of the buyer leg")
+            .takes_value(true)
+            .default_value("1"),
+        cmd::opt_yaml(),
+    ])
+}
+
+fn cmd_check<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand(
+        "check",
+        "Check the write-and-sell pset completed by the buyer before the writer signs it.
+        Checks the deposit, all the opt tokens, the premium paid to the premium address
+        and that the inputs, the outputs and the fee of the writer leg are unchanged.
+        Prints the terms of the three legs",
+    )
+    .args(&cmd::opts_networks())
+    .args(&opts_write_sell())
+    .args(&[
+        cmd::opt("pset", "The base64 write-and-sell pset")
+            .takes_value(true)
+            .required(true),
+        cmd::opt(
+            "write-pset",
+            "The base64 pset of the writer leg, as output by write",
+        )
+        .takes_value(true)
+        .required(true),
+        cmd::opt(
+            "premium-addr",
+            "The address of the writer receiving the premium",
+        )
+        .takes_value(true)
+        .required(true),
+        cmd::opt_yaml(),
+    ])
+}
+
+pub fn execute<'a>(matches: &clap::ArgMatches<'a>) -> Result<(), Error> {
+    match matches.subcommand() {
+        ("sell", Some(ref m)) => exec_sell(m),
+        ("accept", Some(ref m)) => exec_accept(m),
+        ("write", Some(ref m)) => exec_write(m),
+        ("take", Some(ref m)) => exec_take(m),
+        ("check", Some(ref m)) => exec_check(m),
+        (_, _) => unreachable!("clap prints help"),
+    }
+}
+
+// The config of the registered contract or of the config file
+fn option_config<'a>(matches: &clap::ArgMatches<'a>) -> Result<Config, Error> {
+    match matches.value_of("contract") {
+        Some(id) => Ok(cmd::registry(matches)?.get(id)?.config.clone()),
+        None => Config::from_file(matches.value_of("cfg-file").unwrap_or("")),
+    }
+}
+
+fn exec_sell<'a>(matches: &clap::ArgMatches<'a>) -> Result<(), Error> {
+    let cfg = option_config(matches)?;
+    let utxos = funding::utxos_from_file(matches.value_of("utxos").unwrap_or(""))?;
+    let token_utxo = utxos
+        .iter()
+        .find(|u| u.asset == cfg.opt_token)
+        .ok_or(Error::InsufficientFunds(cfg.opt_token, 1))?;
+    let premium = cfg.premium_amount(matches.value_of("premium").unwrap_or(""))?;
+    let premium_addr = cmd::parse_addr_arg(matches, &cfg, "premium-addr")?;
+
+    let pset = offer::sell_offer(token_utxo, premium, cfg.claim_asset, &premium_addr)?;
+    eprintln!("Sign the token input with SINGLE|ANYONECANPAY and send the pset to the buyer");
+    cmd::print_output(
+        matches,
+        &OfferOutput {
+            terms: offer::offer_terms(&pset)?,
+            pset: pset_to_base64(&pset),
+            tx_hex: None,
+            next_step: "elements-cli walletprocesspsbt <pset> true \"SINGLE|ANYONECANPAY\"",
+        },
+    );
+    Ok(())
+}
+
+fn exec_accept<'a>(matches: &clap::ArgMatches<'a>) -> Result<(), Error> {
+    let cfg = option_config(matches)?;
+    let offer_pset = pset_from_base64(matches.value_of("offer-pset").unwrap_or(""))?;
+    let addr = cmd::parse_addr(matches, &cfg)?;
+    let change = cmd::parse_addr_arg(matches, &cfg, "change-addr")?;
+    let utxos = funding::utxos_from_file(matches.value_of("utxos").unwrap_or(""))?;
+    let fee_rate = cmd::parse_arg::<f64>(matches, "fee-rate")?;
+    // The wallet cannot blind the tokens of an input it does not own
+    if addr.blinding_pubkey.is_some() && !matches.is_present("blind") {
+        return Err(Error::ConfidentialAddress);
+    }
+
+    let (mut pset, terms) = offer::accept_offer(
+        offer_pset,
+        cfg.opt_token,
+        &utxos,
+        &addr,
+        &change,
+        cfg.btc_asset,
+        fee_rate,
+    )?;
+    if matches.is_present("blind") {
+        cmd::blind(matches, &mut pset, &utxos)?;
+    }
+    let tx = pset.extract_tx()?;
+    eprintln!(
+        "Buying {} tokens for a premium of {} sats of {}",
+        terms.units, terms.premium, terms.premium_asset
+    );
+    cmd::print_output(
+        matches,
+        &OfferOutput {
+            terms,
+            pset: pset_to_base64(&pset),
+            tx_hex: Some(serialize_hex(&tx)),
+            next_step: "elements-cli walletprocesspsbt <pset> && elements-cli finalizepsbt <pset> \
+                        && elements-cli sendrawtransaction <tx_hex>",
+        },
+    );
+    Ok(())
+}
+
+// The registered contract or the contract of the terms and the config file
+fn write_sell_entry<'a>(matches: &clap::ArgMatches<'a>) -> Result<ContractEntry, Error> {
+    if let Some(id) = matches.value_of("contract") {
+        return Ok(cmd::registry(matches)?.get(id)?.clone());
+    }
+    let (expiry, strike_amt, cfg) = cmd::contract_params(matches)?;
+    let kind = if matches.is_present("put") {
+        OptionKind::Put
+    } else {
+        OptionKind::Call
+    };
+    Ok(ContractEntry::new(kind, expiry, strike_amt, cfg))
+}
+
+// Print the write-and-sell pset with the terms of its legs
+fn print_write_sell<'a>(
+    matches: &clap::ArgMatches<'a>,
+    entry: &ContractEntry,
+    pset: &Pset,
+    next_step: &'static str,
+) -> Result<(), Error> {
+    let contract = entry.contract()?;
+    let params = cmd::address_params(matches, &entry.config)?;
+    cmd::print_output(
+        matches,
+        &WriteSellOutput {
+            contract_id: entry.id()?,
+            deposit_address: contract.deposit_addr(params)?,
+            terms: offer::write_sell_terms(pset, &contract)?,
+            pset: pset_to_base64(pset),
+            next_step,
+        },
+    );
+    Ok(())
+}
+
+fn exec_write<'a>(matches: &clap::ArgMatches<'a>) -> Result<(), Error> {
+    let entry = write_sell_entry(matches)?;
+    let cfg = &entry.config;
+    let buyer_addr = cmd::parse_addr_arg(matches, cfg, "buyer-addr")?;
+    let change = cmd::parse_addr_arg(matches, cfg, "change-addr")?;
+    let premium = cfg.premium_amount(matches.value_of("premium").unwrap_or(""))?;
+    let premium_addr = cmd::parse_addr_arg(matches, cfg, "premium-addr")?;
+    let utxos = funding::utxos_from_file(matches.value_of("utxos").unwrap_or(""))?;
+    let fee_rate = cmd::parse_arg::<f64>(matches, "fee-rate")?;
+
+    let pset = offer::write_and_sell(
+        &entry.contract()?,
+        &utxos,
+        &buyer_addr,
+        premium,
+        cfg.claim_asset,
+        &premium_addr,
+        &change,
+        fee_rate,
+    )?;
+    let mut registry = cmd::registry(matches)?;
+    registry.insert(entry.clone())?;
+    cmd::save_registry(matches, &registry)?;
+
+    eprintln!("Send the pset to the buyer, sign it only after take and check");
+    print_write_sell(
+        matches,
+        &entry,
+        &pset,
+        "options-elements offer take --write-pset <pset>",
+    )
+}
+
+fn exec_take<'a>(matches: &clap::ArgMatches<'a>) -> Result<(), Error> {
+    let entry = write_sell_entry(matches)?;
+    let writer_leg = pset_from_base64(matches.value_of("write-pset").unwrap_or(""))?;
+    let addr = cmd::parse_addr(matches, &entry.config)?;
+    let change = cmd::parse_addr_arg(matches, &entry.config, "change-addr")?;
+    let utxos = funding::utxos_from_file(matches.value_of("utxos").unwrap_or(""))?;
+    let fee_rate = cmd::parse_arg::<f64>(matches, "fee-rate")?;
+
+    let (mut pset, _) = offer::take_write_sell(
+        writer_leg,
+        &entry.contract()?,
+        &utxos,
+        &addr,
+        &change,
+        fee_rate,
+    )?;
+    if matches.is_present("blind") {
+        cmd::blind(matches, &mut pset, &utxos)?;
+    }
+    eprintln!("Both the writer and the buyer check and sign the pset");
+    print_write_sell(
+        matches,
+        &entry,
+        &pset,
+        "options-elements offer check --pset <pset> --write-pset <writer_pset> --premium-addr <addr> \
+         by the writer, elements-cli walletprocesspsbt <pset> by each side, then \
+         elements-cli combinepsbt and finalizepsbt",
+    )
+}
+
+/// Print the terms of the legs of the write-and-sell pset. Fails if a leg is missing
+/// or the buyer changed the writer leg
+fn exec_check<'a>(matches: &clap::ArgMatches<'a>) -> Result<(), Error> {
+    let entry = write_sell_entry(matches)?;
+    let pset = pset_from_base64(matches.value_of("pset").unwrap_or(""))?;
+    let writer_leg = pset_from_base64(matches.value_of("write-pset").unwrap_or(""))?;
+    let premium_addr = cmd::parse_addr_arg(matches, &entry.config, "premium-addr")?;
+    offer::check_write_sell(&pset, &writer_leg, &entry.contract()?, &premium_addr)?;
+    print_write_sell(
+        matches,
+        &entry,
+        &pset,
+        "elements-cli walletprocesspsbt <pset>",
+    )
+}
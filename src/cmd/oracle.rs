@@ -0,0 +1,147 @@
+//! A local oracle attesting the settlement prices of cash-settled options on regtest
+use bitcoin::{self, PrivateKey, PublicKey};
+use hex;
+use serde::Serialize;
+
+use options_elements::{config, oracle};
+use options_elements::{Error, OptionKind};
+
+use cmd;
+
+/// Output of the keygen command
+#[derive(Debug, Serialize)]
+pub struct KeygenOutput {
+    /// The WIF private key signing the prices
+    pub oracle_key: String,
+    /// The public key to configure with `init --oracle-pk`
+    pub oracle_pk: PublicKey,
+}
+
+/// Output of the sign command
+#[derive(Debug, Serialize)]
+pub struct SignOutput {
+    /// The attested grid price
+    pub price: u64,
+    /// The observed price, which settles at the grid price
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub observed_price: Option<u64>,
+    /// The hex price message committing to the option and the price
+    pub message: String,
+    /// The DER hex signature to settle the option with `--oracle-sig`
+    pub oracle_sig: String,
+}
+
+pub fn subcommand<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand_group(
+        "oracle",
+        "Local oracle attesting the settlement prices of cash-settled options for testing",
+    )
+    .subcommand(cmd_keygen())
+    .subcommand(cmd_sign())
+}
+
+fn cmd_keygen<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand("keygen", "Generate a regtest oracle key").args(&[cmd::opt_yaml()])
+}
+
+fn cmd_sign<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand(
+        "sign",
+        "Sign the settlement price of a cash-settled option at expiry",
+    )
+    .args(&[
+        cmd::opt("oracle-key", "The WIF oracle private key")
+            .takes_value(true)
+            .required(true),
+        cmd::opt_expiry().required_unless("contract"),
+        cmd::opt_strike().required_unless("contract"),
+        cmd::opt_cfg_file(),
+        cmd::opt_contract()
+            .required(false)
+            .conflicts_with_all(&["expiry", "strike"]),
+        cmd::opt_registry(),
+        cmd::opt(
+            "put",
+            "Sign the price of a put option instead of a call option",
+        )
+        .takes_value(false)
+        .conflicts_with("contract"),
+        cmd::opt(
+            "price",
+            "The decimal grid price to attest, in units of the claim asset. \
+             Prices off the settlement grid are rejected",
+        )
+        .takes_value(true)
+        .required_unless("observed-price"),
+        cmd::opt(
+            "observed-price",
+            "The decimal price observed at expiry. Attests the nearest price of the \
+             settlement grid, the lower one of two equally near prices",
+        )
+        .takes_value(true)
+        .conflicts_with("price"),
+        cmd::opt_yaml(),
+    ])
+}
+
+pub fn execute<'a>(matches: &clap::ArgMatches<'a>) -> Result<(), Error> {
+    match matches.subcommand() {
+        ("keygen", Some(ref m)) => exec_keygen(m),
+        ("sign", Some(ref m)) => exec_sign(m),
+        (_, _) => unreachable!("clap prints help"),
+    }
+}
+
+fn exec_keygen<'a>(matches: &clap::ArgMatches<'a>) -> Result<(), Error> {
+    let secp = bitcoin::secp256k1::Secp256k1::signing_only();
+    let oracle_key = config::new_private_key();
+    cmd::print_output(
+        matches,
+        &KeygenOutput {
+            oracle_key: oracle_key.to_wif(),
+            oracle_pk: oracle_key.public_key(&secp),
+        },
+    );
+    Ok(())
+}
+
+fn exec_sign<'a>(matches: &clap::ArgMatches<'a>) -> Result<(), Error> {
+    let key_wif = matches.value_of("oracle-key").unwrap_or("");
+    let oracle_key = PrivateKey::from_wif(key_wif)
+        .map_err(|_| Error::InvalidArg("oracle-key", key_wif.to_owned()))?;
+    let entry = match matches.value_of("contract") {
+        Some(id) => cmd::registry(matches)?.get(id)?.clone(),
+        None if matches.is_present("put") => cmd::contract_entry(matches, OptionKind::Put)?,
+        None => cmd::contract_entry(matches, OptionKind::Call)?,
+    };
+    let contract = entry.original_contract();
+    let observed_price = match matches.value_of("observed-price") {
+        Some(observed) => Some(entry.config.price_amount(observed)?),
+        None => None,
+    };
+    let price = match observed_price {
+        Some(observed) => contract.grid_price(observed)?,
+        None => {
+            let price = entry
+                .config
+                .price_amount(matches.value_of("price").unwrap_or(""))?;
+            // A signature of a price off the grid can never settle the option
+            if contract.grid_price(price)? != price {
+                return Err(Error::InvalidSettlementPrice(price));
+            }
+            price
+        }
+    };
+    let msg = contract.price_message(price)?;
+    let sig = oracle::sign_price(&oracle_key, &msg);
+    cmd::print_output(
+        matches,
+        &SignOutput {
+            price,
+            observed_price,
+            message: msg.to_string(),
+            oracle_sig: hex::encode(&sig.serialize_der()[..]),
+        },
+    );
+    Ok(())
+}
@@ -0,0 +1,158 @@
+//! Create a put option on Elements
+//!
+//! A put uses the same covenant as the call option, with the assets swapped:
+//! the strike amount of the claim asset is locked in the contract and the
+//! option holder sells `locked_asset_amount` of the lock asset to exercise.
+use cmd;
+use cmd::call;
+use options_elements::{ContractEntry, Error, OptionKind};
+
+pub fn subcommand<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand_group("put", "Put options functions")
+        .subcommand(cmd_create())
+        .subcommand(cmd_exercise())
+        .subcommand(cmd_cancel())
+        .subcommand(cmd_expiry())
+        .subcommand(cmd_addcontract())
+        .subcommand(cmd_finalize())
+        .subcommand(cmd_verify())
+        .subcommand(cmd_claim_bene())
+        .subcommand(call::cmd_chain())
+}
+
+fn cmd_create<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand("create", "Create a bitcoin put option")
+        .args(&cmd::opts_networks())
+        .args(&cmd::opts_contract())
+        .args(&[cmd::opt_yaml()])
+}
+
+fn cmd_exercise<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand(
+        "exercise",
+        "Exercise a put option. Outputs a partial base64 pset
+        that should be funded by the wallet before passing it to addcontract.
+        Elements wallet does not understand covenants so it cannot estimate fees
+        for the covenant input. Fund at the target fee rate and pass it to
+        addcontract with --fee-rate to pay the fee of the covenant input\n
+        Example usage: elements-cli fundrawtransaction <tx_hex> '''{\"feeRate\": 0.00001}'''
+        for 1 sat/vB and elements-cli converttopsbt <funded_hex>",
+    )
+    .args(&cmd::opts_networks())
+    .args(&cmd::opts_contract())
+    .args(&cmd::opts_execute())
+    .args(&cmd::opts_funding())
+    .args(&cmd::opts_blinding())
+    .args(&[cmd::opt_yaml()])
+}
+
+fn cmd_cancel<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand(
+        "cancel",
+        "Cancel a put option. Outputs a partial base64 pset
+        that should be funded by the wallet before passing it to addcontract.
+        Elements wallet does not understand covenants so it cannot estimate fees
+        for the covenant input. Fund at the target fee rate and pass it to
+        addcontract with --fee-rate to pay the fee of the covenant input\n
+        Example usage: elements-cli fundrawtransaction <tx_hex> '''{\"feeRate\": 0.00001}'''
+        for 1 sat/vB and elements-cli converttopsbt <funded_hex>",
+    )
+    .args(&cmd::opts_networks())
+    .args(&cmd::opts_contract())
+    .args(&cmd::opts_execute())
+    .args(&cmd::opts_funding())
+    .args(&cmd::opts_blinding())
+    .args(&[cmd::opt_yaml()])
+}
+
+fn cmd_expiry<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand(
+        "expiry",
+        "Claim a put option that has been expired. Outputs a partial base64 pset
+        that should be funded by the wallet before passing it to addcontract.
+        Elements wallet does not understand covenants so it cannot estimate fees
+        for the covenant input. Fund at the target fee rate and pass it to
+        addcontract with --fee-rate to pay the fee of the covenant input\n
+        Example usage: elements-cli fundrawtransaction <tx_hex> '''{\"feeRate\": 0.00001}'''
+        for 1 sat/vB and elements-cli converttopsbt <funded_hex>",
+    )
+    .args(&cmd::opts_networks())
+    .args(&cmd::opts_contract())
+    .args(&cmd::opts_execute())
+    .args(&cmd::opts_funding())
+    .args(&cmd::opts_blinding())
+    .args(&[cmd::opt_yaml()])
+}
+
+fn cmd_addcontract<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand(
+        "addcontract",
+        "Step 2 for creating a transaction a put option. Provide a funded pset created by
+        funding the output pset of exercise/expiry/cancel step.
+        Provide covenant contract prevout and destination address for receiving locked asset.
+        Incase of claimbene, the prevout is *NOT* the contract prevout, but the prevout created
+        after contract is exercised.",
+    )
+    .args(&cmd::opts_networks())
+    .args(&cmd::opts_contract())
+    .args(&cmd::opts_addcontract())
+    .args(&cmd::opts_blinding())
+    .args(&[cmd::opt_yaml()])
+}
+
+fn cmd_finalize<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand(
+        "finalize",
+        "Final step for creating a transaction when dealing with put option.
+        Sends the locked asset to the address supplied in add contract.
+        Extract the output pset of this step with finalizepsbt and broadcast it.",
+    )
+    .args(&cmd::opts_networks())
+    .args(&cmd::opts_contract())
+    .args(&cmd::opts_finalize())
+    .args(&[cmd::opt_yaml()])
+}
+
+fn cmd_verify<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand(
+        "verify",
+        "Check that the finalized transaction satisfies the covenant of the put option.
+        Reports each failed after(), outputs_pref(), signature or oracle check",
+    )
+    .args(&cmd::opts_contract())
+    .args(&cmd::opts_verify())
+    .args(&[cmd::opt_yaml()])
+}
+
+fn cmd_claim_bene<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand(
+        "claimbene",
+        "Claim the underlying asset paid with the bene token after exercise",
+    )
+    .args(&cmd::opts_networks())
+    .args(&cmd::opts_contract())
+    .args(&cmd::opts_execute())
+    .args(&cmd::opts_funding())
+    .args(&cmd::opts_blinding())
+    .args(&[cmd::opt_yaml()])
+}
+
+pub fn execute<'a>(matches: &clap::ArgMatches<'a>) -> Result<(), Error> {
+    match matches.subcommand() {
+        ("create", Some(ref m)) => call::exec_create(m, &entry_from_matches(m)?),
+        ("exercise", Some(ref m)) => call::exec_exercise(m, &entry_from_matches(m)?),
+        ("expiry", Some(ref m)) => call::exec_expiry(m, &entry_from_matches(m)?),
+        ("cancel", Some(ref m)) => call::exec_cancel(m, &entry_from_matches(m)?),
+        ("addcontract", Some(ref m)) => call::exec_addcontract(m, &entry_from_matches(m)?),
+        ("finalize", Some(ref m)) => call::exec_finalize(m, &entry_from_matches(m)?),
+        ("verify", Some(ref m)) => call::exec_verify(m, &entry_from_matches(m)?),
+        ("claimbene", Some(ref m)) => call::exec_claim_bene(m, &entry_from_matches(m)?),
+        ("chain", Some(ref m)) => call::exec_chain(m, OptionKind::Put),
+        (_, _) => unreachable!("clap prints help"),
+    }
+}
+
+/// Read the put option from the registry or the command line arguments and the config file
+fn entry_from_matches(matches: &clap::ArgMatches) -> Result<ContractEntry, Error> {
+    cmd::contract_entry(matches, OptionKind::Put)
+}
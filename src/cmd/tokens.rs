@@ -0,0 +1,166 @@
+//! Issue and verify the opt and bene tokens of an option
+use elements::encode::{deserialize, serialize_hex};
+use elements::{AssetId, ContractHash, Transaction};
+use hex;
+use serde::Serialize;
+
+use options_elements::contract::pset_to_base64;
+use options_elements::{funding, issuance, Config, Error, OptionKind};
+
+use cmd;
+
+/// Output of the issue command
+#[derive(Debug, Serialize)]
+pub struct IssueOutput {
+    pub opt_token: AssetId,
+    pub bene_token: AssetId,
+    /// The contract hash of the opt token issuance, the sha256 of the option terms
+    pub opt_contract_hash: ContractHash,
+    /// The contract hash of the bene token issuance, the sha256 of the option terms
+    pub bene_contract_hash: ContractHash,
+    /// The base64 encoded unsigned pset issuing the tokens
+    pub pset: String,
+    pub tx_hex: String,
+    pub next_step: &'static str,
+}
+
+/// The kind of option selected by the `put` flag
+fn option_kind<'a>(matches: &clap::ArgMatches<'a>) -> OptionKind {
+    if matches.is_present("put") {
+        OptionKind::Put
+    } else {
+        OptionKind::Call
+    }
+}
+
+pub fn cmd_issue<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand(
+        "issue",
+        "Issue the opt and bene tokens of an option with the supply of the config \
+         and no reissuance tokens. Writes the token asset IDs to the config file",
+    )
+    .args(&cmd::opts_networks())
+    .args(&cmd::opts_blinding())
+    .args(&[
+        cmd::opt_expiry().required(true),
+        cmd::opt_strike().required(true),
+        cmd::opt_cfg_file(),
+        cmd::opt(
+            "put",
+            "Issue the tokens of a put option instead of a call option",
+        )
+        .takes_value(false),
+        cmd::opt("addr", "The address receiving the issued tokens")
+            .takes_value(true)
+            .required(true),
+        cmd::opt(
+            "utxos",
+            "Path of a JSON list of utxos(txid, vout, asset, value, asset_blinder, \
+             value_blinder, script_pubkey) to issue the tokens from and fund the fee",
+        )
+        .takes_value(true)
+        .required(true),
+        cmd::opt(
+            "change-addr",
+            "The address receiving the change of the utxos",
+        )
+        .takes_value(true)
+        .required(true),
+        cmd::opt("fee-rate", "The fee rate in sat/vB of the issuance")
+            .takes_value(true)
+            .default_value("1"),
+        cmd::opt_yaml(),
+    ])
+}
+
+pub fn exec_issue<'a>(matches: &clap::ArgMatches<'a>) -> Result<(), Error> {
+    let (expiry, strike_amt, mut cfg) = cmd::contract_params(matches)?;
+    let kind = option_kind(matches);
+    let addr = cmd::parse_addr(matches, &cfg)?;
+    let change = cmd::parse_addr_arg(matches, &cfg, "change-addr")?;
+    let utxos = funding::utxos_from_file(matches.value_of("utxos").unwrap_or(""))?;
+    let fee_rate = cmd::parse_arg::<f64>(matches, "fee-rate")?;
+
+    let mut issued = issuance::issue_tokens(
+        kind, expiry, strike_amt, &cfg, &utxos, &addr, &change, fee_rate,
+    )?;
+    if matches.is_present("blind") {
+        cmd::blind(matches, &mut issued.pset, &utxos)?;
+    }
+
+    // The asset IDs only depend on the issuing outpoints, so the config can be
+    // updated before the transaction is broadcast
+    cfg.opt_token = issued.opt_token;
+    cfg.bene_token = issued.bene_token;
+    cfg.to_file(matches.value_of("cfg-file").unwrap_or(""))?;
+
+    let tx = issued.pset.extract_tx()?;
+    eprintln!("Wrote the token asset IDs to the config file");
+    cmd::print_output(
+        matches,
+        &IssueOutput {
+            opt_token: issued.opt_token,
+            bene_token: issued.bene_token,
+            opt_contract_hash: issued.opt_terms.contract_hash(),
+            bene_contract_hash: issued.bene_terms.contract_hash(),
+            pset: pset_to_base64(&issued.pset),
+            tx_hex: serialize_hex(&tx),
+            next_step: "Blind and sign the pset with the wallet owning the utxos, \
+                        then broadcast it and create the option",
+        },
+    );
+    Ok(())
+}
+
+pub fn cmd_verify_tokens<'a>() -> clap::App<'a, 'a> {
+    cmd::subcommand(
+        "verify-tokens",
+        "Verify that the opt and bene tokens of the config are issued by a transaction \
+         with the supply of the config and no reissuance tokens",
+    )
+    .args(&[
+        cmd::opt(
+            "tx-hex",
+            "The raw hex of the transaction issuing the tokens",
+        )
+        .takes_value(true)
+        .required(true),
+        cmd::opt_cfg_file(),
+        cmd::opt_expiry().requires("strike"),
+        cmd::opt_strike().requires("expiry"),
+        cmd::opt(
+            "put",
+            "The expiry and strike are of a put option instead of a call option",
+        )
+        .takes_value(false)
+        .requires("expiry"),
+        cmd::opt_yaml(),
+    ])
+}
+
+/// Prints the verification report of each token. Fails if any token fails verification
+pub fn exec_verify_tokens<'a>(matches: &clap::ArgMatches<'a>) -> Result<(), Error> {
+    let tx_hex = matches.value_of("tx-hex").unwrap_or("");
+    let tx: Transaction = hex::decode(tx_hex)
+        .ok()
+        .and_then(|bytes| deserialize(&bytes).ok())
+        .ok_or_else(|| Error::InvalidArg("tx-hex", tx_hex.to_owned()))?;
+
+    let (cfg, option) = if matches.is_present("expiry") {
+        let (expiry, strike_amt, cfg) = cmd::contract_params(matches)?;
+        (cfg, Some((option_kind(matches), expiry, strike_amt)))
+    } else {
+        (
+            Config::from_file(matches.value_of("cfg-file").unwrap_or(""))?,
+            None,
+        )
+    };
+
+    let reports = issuance::verify_tokens(&tx, &cfg, option);
+    cmd::print_output(matches, &reports);
+    if reports.iter().all(|r| r.pass) {
+        Ok(())
+    } else {
+        Err(Error::UnverifiedTokens)
+    }
+}
@@ -0,0 +1,226 @@
+//! Configuration of the assets and tokens used by the option contracts
+use bitcoin;
+use elements::{secp256k1_zkp, AssetId};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::Write;
+use std::str::FromStr;
+
+use contract::Error;
+use network::Network;
+
+/// The control secret key of public covenants. Known to all participants
+pub const CTRL_SK: &str = "cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy";
+
+/// The control public key corresponding to `CTRL_SK`
+pub const CTRL_PK: &str = "039b6347398505f5ec93826dc61c19f47c66c0283ee9be980e29ce325a0f4679ef";
+
+/// The regtest btc asset id
+pub const BTC_ASSET: [u8; 32] = [
+    0x23, 0x0f, 0x4f, 0x5d, 0x4b, 0x7c, 0x6f, 0xa8, 0x45, 0x80, 0x6e, 0xe4, 0xf6, 0x77, 0x13, 0x45,
+    0x9e, 0x1b, 0x69, 0xe8, 0xe6, 0x0f, 0xce, 0xe2, 0xe4, 0x94, 0x0c, 0x7a, 0x0d, 0x5d, 0xe1, 0xb2,
+];
+
+/// When the option can be exercised
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OptionStyle {
+    /// Exercisable at any time until the expiry is claimed
+    American,
+    /// Exercisable from the start of the exercise window before expiry until
+    /// the expiry is claimed
+    European,
+}
+
+impl Default for OptionStyle {
+    fn default() -> OptionStyle {
+        OptionStyle::American
+    }
+}
+
+impl FromStr for OptionStyle {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "american" => Ok(OptionStyle::American),
+            "european" => Ok(OptionStyle::European),
+            _ => Err(Error::InvalidArg("option-style", s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    pub lock_asset: AssetId,
+    pub claim_asset: AssetId,
+    pub opt_token: AssetId,
+    pub bene_token: AssetId,
+    pub locked_asset_amount: u64,
+    pub control_pk: bitcoin::PublicKey,
+    /// The secret of the control key when it is public. The secret of a private
+    /// control key is kept in a separate keyfile instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub control_sk: Option<bitcoin::PrivateKey>,
+    pub btc_asset: AssetId,
+    /// The network of the contract addresses. Defaults to elementsregtest
+    #[serde(default)]
+    pub network: Network,
+    /// The number of decimals of the claim asset the strike is given in.
+    /// Defaults to 8 like the btc asset
+    #[serde(default = "default_precision")]
+    pub claim_asset_precision: u8,
+    /// The supply of the opt and bene tokens. Each token covers an equal share
+    /// of the locked asset and can be exercised separately. Defaults to 1
+    #[serde(default = "default_supply")]
+    pub opt_token_supply: u64,
+    /// The exercise style of the options. Defaults to american
+    #[serde(default)]
+    pub option_style: OptionStyle,
+    /// The length of the exercise window of european options, in blocks for
+    /// expiries given as a height and in seconds otherwise
+    #[serde(default)]
+    pub exercise_window: u32,
+    /// The key of the oracle attesting the settlement price of cash-settled
+    /// options. Options are physically settled when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oracle_pk: Option<bitcoin::PublicKey>,
+    /// The prices cash-settled options can be settled at, in sats of the claim
+    /// asset for the locked asset amount
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub settlement_prices: Vec<u64>,
+}
+
+/// The default precision of assets
+pub const DEFAULT_PRECISION: u8 = 8;
+
+fn default_precision() -> u8 {
+    DEFAULT_PRECISION
+}
+
+fn default_supply() -> u64 {
+    1
+}
+
+/// Parse the decimal `value` as an integer amount scaled by `10^precision`.
+/// Returns `None` if the value is malformed, has more significant decimals
+/// than `precision` or overflows.
+pub fn parse_decimal(value: &str, precision: u8) -> Option<u64> {
+    let mut parts = value.splitn(2, '.');
+    let int = parts.next().unwrap_or("");
+    let frac = parts.next().unwrap_or("").trim_end_matches('0');
+    let is_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    if int.is_empty() || !is_digits(int) || !is_digits(frac) || frac.len() > precision as usize {
+        return None;
+    }
+    let scale = 10u64.checked_pow(precision as u32)?;
+    let frac_amt = if frac.is_empty() {
+        0
+    } else {
+        let frac_scale = 10u64.pow((precision as usize - frac.len()) as u32);
+        frac.parse::<u64>().ok()? * frac_scale
+    };
+    int.parse::<u64>()
+        .ok()?
+        .checked_mul(scale)?
+        .checked_add(frac_amt)
+}
+
+/// A new random key on regtest, for private control keys and the local oracle
+pub fn new_private_key() -> bitcoin::PrivateKey {
+    let mut rng = secp256k1_zkp::rand::thread_rng();
+    bitcoin::PrivateKey {
+        compressed: true,
+        network: bitcoin::Network::Regtest,
+        key: secp256k1_zkp::SecretKey::new(&mut rng),
+    }
+}
+
+/// Read the WIF private key in the keyfile at `path`
+pub fn read_keyfile(path: &str) -> Result<bitcoin::PrivateKey, Error> {
+    let wif = fs::read_to_string(path).map_err(|e| Error::KeyFile(path.to_owned(), e))?;
+    bitcoin::PrivateKey::from_wif(wif.trim()).map_err(|_| Error::MalformedKeyFile(path.to_owned()))
+}
+
+/// Write the WIF private key to the keyfile at `path`, only readable by the owner
+pub fn write_keyfile(path: &str, key: &bitcoin::PrivateKey) -> Result<(), Error> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .map_err(|e| Error::KeyFile(path.to_owned(), e))?;
+    writeln!(file, "{}", key.to_wif()).map_err(|e| Error::KeyFile(path.to_owned(), e))
+}
+
+impl Config {
+    /// Read the YAML config file at `path`
+    pub fn from_file(path: &str) -> Result<Config, Error> {
+        let file = File::open(path).map_err(|e| Error::ConfigFile(path.to_owned(), e))?;
+        serde_yaml::from_reader(file).map_err(Error::MalformedConfig)
+    }
+
+    /// The strike amount in the smallest unit of the claim asset for the
+    /// decimal `strike`
+    pub fn strike_amount(&self, strike: &str) -> Result<u64, Error> {
+        parse_decimal(strike, self.claim_asset_precision)
+            .ok_or_else(|| Error::InvalidArg("strike", strike.to_owned()))
+    }
+
+    /// The premium in the smallest unit of the claim asset for the decimal `premium`
+    pub fn premium_amount(&self, premium: &str) -> Result<u64, Error> {
+        parse_decimal(premium, self.claim_asset_precision)
+            .ok_or_else(|| Error::InvalidArg("premium", premium.to_owned()))
+    }
+
+    /// The settlement price in the smallest unit of the claim asset for the
+    /// decimal `price`
+    pub fn price_amount(&self, price: &str) -> Result<u64, Error> {
+        parse_decimal(price, self.claim_asset_precision)
+            .ok_or_else(|| Error::InvalidArg("price", price.to_owned()))
+    }
+
+    /// Write the config as YAML to the file at `path`
+    pub fn to_file(&self, path: &str) -> Result<(), Error> {
+        let file = File::create(path).map_err(|e| Error::ConfigFile(path.to_owned(), e))?;
+        serde_yaml::to_writer(file, self).map_err(Error::MalformedConfig)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use elements::secp256k1_zkp;
+    use std::str::FromStr;
+
+    use super::*;
+    #[test]
+    fn test_keys() {
+        let priv_key = bitcoin::PrivateKey::from_wif(CTRL_SK).expect("Known private key");
+        let secp = secp256k1_zkp::Secp256k1::signing_only();
+        let pk = bitcoin::PublicKey::from_str(CTRL_PK).unwrap();
+        assert_eq!(pk, priv_key.public_key(&secp));
+    }
+
+    #[test]
+    fn test_parse_decimal() {
+        assert_eq!(parse_decimal("100", 8), Some(10_000_000_000));
+        assert_eq!(parse_decimal("100.5", 2), Some(10_050));
+        assert_eq!(parse_decimal("0.00000001", 8), Some(1));
+        assert_eq!(parse_decimal("100.000000000", 8), Some(10_000_000_000));
+        assert_eq!(parse_decimal("1.", 0), Some(1));
+        // Not representable with the precision
+        assert_eq!(parse_decimal("100.000000001", 8), None);
+        assert_eq!(parse_decimal("100.001", 2), None);
+        // Malformed or overflowing
+        assert_eq!(parse_decimal(".5", 8), None);
+        assert_eq!(parse_decimal("-1", 8), None);
+        assert_eq!(parse_decimal("1e3", 8), None);
+        assert_eq!(parse_decimal("1.2.3", 8), None);
+        assert_eq!(parse_decimal("200000000000", 8), None);
+        assert_eq!(parse_decimal("1", 20), None);
+    }
+}
@@ -0,0 +1,2305 @@
+//! Call and put option contracts on Elements
+//!
+//! The contract locks an asset in a covenant which can only be spent by
+//! exercising(burning the opt token and paying the strike), cancelling(burning
+//! both tokens) or after expiry(burning the bene token).
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::{PrivateKey, PublicKey};
+use elements::pset::{self, PartiallySignedTransaction as Pset};
+use elements::{
+    confidential, encode::serialize, encode::serialize_hex, secp256k1_zkp, Address, AddressParams,
+    AssetId, OutPoint, Script, SigHashType, Transaction, TxOut,
+};
+use miniscript::pset::PsetInputSatisfier;
+use miniscript::{descriptor::CovenantDescriptor, Segwitv0};
+use miniscript::{Descriptor, DescriptorTrait, Miniscript, Satisfier};
+
+use std::{error, fmt, io, str::FromStr};
+
+use config::{Config, OptionStyle};
+use network::Network;
+use oracle::{self, CashSettlement};
+use rpc;
+
+/// The operation performed on a contract when spending it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpendType {
+    /// Burn the opt token and pay the strike to receive the locked asset
+    Exercise,
+    /// Burn both tokens to unlock the locked asset
+    Cancel,
+    /// Burn the bene token after expiry to reclaim the locked asset
+    Expiry,
+    /// Burn the bene token to claim the strike paid at exercise
+    ClaimBene,
+}
+
+impl FromStr for SpendType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exercise" => Ok(SpendType::Exercise),
+            "cancel" => Ok(SpendType::Cancel),
+            "expiry" => Ok(SpendType::Expiry),
+            "claimbene" => Ok(SpendType::ClaimBene),
+            _ => Err(Error::InvalidSpendType(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for SpendType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SpendType::Exercise => f.write_str("exercise"),
+            SpendType::Cancel => f.write_str("cancel"),
+            SpendType::Expiry => f.write_str("expiry"),
+            SpendType::ClaimBene => f.write_str("claimbene"),
+        }
+    }
+}
+
+/// Paramerters for the Option Asset being traded
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OptAssetParams {
+    /// The asset being traded
+    asset: AssetId,
+    /// The options token for this address
+    /// Beneficiary token or options claim token
+    opt_tkn: AssetId,
+    /// Value of the asset being traded
+    value: u64,
+}
+
+impl OptAssetParams {
+    pub fn new(asset: AssetId, opt_tkn: AssetId, value: u64) -> Self {
+        Self {
+            asset,
+            opt_tkn,
+            value,
+        }
+    }
+
+    /// The asset being traded
+    pub fn asset(&self) -> AssetId {
+        self.asset
+    }
+
+    /// The token associated with the asset
+    pub fn opt_tkn(&self) -> AssetId {
+        self.opt_tkn
+    }
+
+    /// Value of the asset being traded
+    pub fn value(&self) -> u64 {
+        self.value
+    }
+}
+
+// Satisfies the csfs fragment of the settlement branch with the oracle signature
+// of the settled price. The oracle signs the price message instead of the
+// transaction, so the signature is not a partial signature of the covenant input.
+struct OracleSatisfier {
+    oracle_pk: PublicKey,
+    msg: sha256::Hash,
+    sig: secp256k1_zkp::Signature,
+}
+
+impl Satisfier<PublicKey> for OracleSatisfier {
+    fn lookup_csfs_sig(
+        &self,
+        pk: &PublicKey,
+        msg: &sha256::Hash,
+    ) -> Option<secp256k1_zkp::Signature> {
+        if *pk == self.oracle_pk && *msg == self.msg {
+            Some(self.sig)
+        } else {
+            None
+        }
+    }
+}
+
+/// Lock times below this value are block heights, others are unix timestamps
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+/// The output of the exercise transaction paying the strike, claimed with claimbene
+pub const EXERCISE_PAYOUT_VOUT: u32 = 1;
+/// The output of a partial exercise transaction re-locking the remaining collateral
+pub const SUCCESSOR_VOUT: u32 = 2;
+
+/// Maximum size of the witness script of a standard p2wsh input
+pub const MAX_STANDARD_P2WSH_SCRIPT_SIZE: usize = 3600;
+
+// The W fragment of a thresh requiring the txouts `outputs` at the first outputs.
+// A european exercise also requires the lock time to be past `exercise_start`.
+fn exercise_cond(exercise_start: Option<u32>, outputs: &str) -> String {
+    match exercise_start {
+        Some(start) => format!("al:and_b(n:after({}),atv:outputs_pref({}))", start, outputs),
+        None => format!("altv:outputs_pref({})", outputs),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionContract {
+    /// Time in bitcoin is represented as u32. Either a block height
+    /// or a unix timestamp as in `nLockTime`
+    expiry: u32,
+    /// The writer asset params
+    locked_asset_params: OptAssetParams,
+    /// The buyer asset params
+    claim_asset_params: OptAssetParams,
+    /// The control key
+    /// This key does the check sig from stack operation and
+    /// verifies the covenant
+    /// In our construction this is public key whose private
+    /// key is known by everyone. We can also use this to
+    /// restrict the people we want to participate in the
+    /// options contract
+    control_key: PublicKey,
+    /// The secret key corresponding to the above Pk
+    /// This is not really a secret for public contracts, but known to all
+    /// participants. Permissioned contracts load it from a keyfile
+    control_sk: Option<PrivateKey>,
+    /// The btc hard coded address
+    /// Required for fees. Usually this would be the locked asset, but it's
+    /// not necessary
+    btc_asset: AssetId,
+    /// The number of option(and bene) tokens covering the locked asset. Each
+    /// token exercises 1/units of the locked asset for 1/units of the strike
+    units: u64,
+    /// The number of tokens exercised by the exercise operation or claimed by
+    /// the claimbene operation
+    exercise_units: u64,
+    /// The exercise window of european options, opening this many blocks or
+    /// seconds before expiry like the expiry. The window has no upper bound, the
+    /// option can be exercised until the expiry is claimed. American options can
+    /// be exercised at any time
+    exercise_window: Option<u32>,
+    /// The oracle settlement of cash-settled options. Physically settled
+    /// options are exercised by paying the strike
+    cash_settlement: Option<CashSettlement>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    ExpectedExplicitAsset,
+    MiniscriptErr(miniscript::Error),
+    InvalidClaimTx,
+    /// Contract operation must be exercise/expiry/cancel/claimbene
+    InvalidSpendType(String),
+    /// The funded transaction does not contain the output required by the covenant
+    MissingCovOutput(&'static str),
+    /// The serialized outputs exceed the 520 byte covenant limit
+    CovOutputsTooLarge(usize),
+    /// Claiming the bene output to a confidential address without blinding it in the tool
+    ConfidentialAddress,
+    /// The pset does not contain any input to spend the covenant
+    MissingCovInput,
+    /// Malformed base64 encoding of the pset
+    Base64(base64::DecodeError),
+    /// Malformed pset serialization
+    PsetEncoding(elements::encode::Error),
+    PsetErr(elements::pset::Error),
+    /// Error while finalizing the covenant input
+    FinalizeErr(miniscript::pset::Error),
+    /// Error from the elementsd rpc
+    Rpc(rpc::Error),
+    /// The covenant output is present, but not at the position required by the covenant
+    WrongOutputPosition(&'static str, usize),
+    /// The config file at the path cannot be read or written
+    ConfigFile(String, io::Error),
+    /// The config file is not valid YAML for `Config`
+    MalformedConfig(serde_yaml::Error),
+    /// Malformed command line argument with the argument name and value
+    InvalidArg(&'static str, String),
+    /// The registry file at the path cannot be read or written
+    RegistryFile(String, io::Error),
+    /// The registry file is not valid YAML for `Registry`
+    MalformedRegistry(serde_yaml::Error),
+    /// No contract with the ID in the registry
+    UnknownContract(String),
+    /// The address is not valid for the network of the contract
+    WrongNetwork(String, Network),
+    /// The locked amount and the strike cannot be split into the number of tokens
+    IndivisibleAmount(u64),
+    /// The number of tokens to exercise must be between 1 and the remaining tokens
+    InvalidExerciseUnits(u64, u64),
+    /// The exercise window of a european option must be non empty and start
+    /// before the expiry with a lock time of the same type
+    InvalidExerciseWindow(u32),
+    /// Error reading or writing the control keyfile at the path
+    KeyFile(String, io::Error),
+    /// The keyfile at the path does not contain a WIF private key
+    MalformedKeyFile(String),
+    /// The contract has a private control key and no keyfile was given
+    MissingControlKey,
+    /// The key of the keyfile is not the control key of the contract
+    InvalidControlKey,
+    /// The operation on a cash-settled option needs the attested settlement price
+    MissingSettlement,
+    /// Cash-settled options settle at the expiry price and cannot be european
+    EuropeanCashSettlement,
+    /// The price is not in the settlement prices of the cash-settled option
+    InvalidSettlementPrice(u64),
+    /// The oracle signature does not attest the settlement price
+    InvalidOracleSig,
+    /// The funded transaction does not contain an explicit fee output
+    MissingFeeOutput,
+    /// No output that is not constrained by the covenant can pay the additional fee
+    InsufficientFee(u64),
+    /// The utxo file at the path cannot be read
+    UtxoFile(String, io::Error),
+    /// The utxo file is not a JSON list of `Utxo`
+    MalformedUtxos(serde_json::Error),
+    /// The utxo cannot be spent by the funding with the reason
+    InvalidUtxo(OutPoint, &'static str),
+    /// The utxos do not cover the amount in sats of the asset
+    InsufficientFunds(AssetId, u64),
+    /// The transaction needs at least the number of utxos
+    TooFewUtxos(usize),
+    /// The blinding factors of the confidential input spending the outpoint are not known
+    MissingInputSecrets(OutPoint),
+    /// Error while creating the commitments or proofs of a blinded output
+    BlindingErr(secp256k1_zkp::Error),
+    /// The tokens of the config are not issued as required by the transaction
+    UnverifiedTokens,
+    /// The transaction does not satisfy the covenant of the contract
+    UnsatisfiedCovenant,
+    /// The opt and bene tokens are not issued yet, or are the same asset
+    UnissuedTokens,
+    /// The covenant of the number of tokens exceeds the standard witness script
+    /// size, with the maximum number of tokens
+    TooManyUnits(u64, u64),
+    /// The covenant is not an option covenant, with the reason
+    MalformedCovenant(&'static str),
+    /// The pset is not a signed offer selling tokens for a premium, with the reason
+    InvalidOffer(&'static str),
+}
+
+impl Error {
+    /// The process exit code for the error. Errors of the same class share
+    /// an exit code so that scripts can branch on the failure class.
+    /// Exit code 1 is reserved for unexpected failures(panics).
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            Error::InvalidArg(..)
+            | Error::InvalidSpendType(..)
+            | Error::UnknownContract(..)
+            | Error::WrongNetwork(..)
+            | Error::IndivisibleAmount(..)
+            | Error::InvalidExerciseUnits(..)
+            | Error::InvalidExerciseWindow(..)
+            | Error::MissingSettlement
+            | Error::EuropeanCashSettlement
+            | Error::MissingControlKey
+            | Error::InvalidControlKey
+            | Error::InvalidSettlementPrice(..)
+            | Error::InvalidOracleSig
+            | Error::MissingInputSecrets(..) => 2,
+            Error::ConfigFile(..)
+            | Error::MalformedConfig(..)
+            | Error::KeyFile(..)
+            | Error::MalformedKeyFile(..)
+            | Error::RegistryFile(..)
+            | Error::MalformedRegistry(..)
+            | Error::UtxoFile(..)
+            | Error::MalformedUtxos(..)
+            | Error::InvalidUtxo(..) => 3,
+            Error::Base64(..) | Error::PsetEncoding(..) => 4,
+            Error::MissingCovOutput(..)
+            | Error::WrongOutputPosition(..)
+            | Error::MissingCovInput
+            | Error::InvalidClaimTx
+            | Error::MissingFeeOutput
+            | Error::InsufficientFee(..)
+            | Error::InsufficientFunds(..)
+            | Error::TooFewUtxos(..)
+            | Error::MalformedCovenant(..)
+            | Error::InvalidOffer(..) => 5,
+            Error::CovOutputsTooLarge(..) | Error::TooManyUnits(..) => 6,
+            Error::ConfidentialAddress => 7,
+            Error::ExpectedExplicitAsset
+            | Error::MiniscriptErr(..)
+            | Error::PsetErr(..)
+            | Error::FinalizeErr(..)
+            | Error::BlindingErr(..) => 8,
+            Error::Rpc(..) => 9,
+            Error::UnverifiedTokens | Error::UnissuedTokens => 10,
+            Error::UnsatisfiedCovenant => 11,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::ExpectedExplicitAsset => {
+                write!(f, "Expected an Explicit Asset for creating Options")
+            }
+            Error::MiniscriptErr(ref e) => {
+                write!(f, "Inner Miniscript Error: {}", e)
+            }
+            Error::InvalidClaimTx => {
+                write!(
+                    f,
+                    "Fund transaction does not contain the locked collateral output"
+                )
+            }
+            Error::InvalidSpendType(ref ty) => {
+                write!(
+                    f,
+                    "Type must be expiry/exercise/cancel/claimbene, found {}",
+                    ty
+                )
+            }
+            Error::MissingCovOutput(out) => {
+                write!(f, "Tx must contain {} txout", out)
+            }
+            Error::CovOutputsTooLarge(len) => {
+                write!(
+                    f,
+                    "tx output len {} exceeds 520, try sending to a non-confidential address instead",
+                    len
+                )
+            }
+            Error::ConfidentialAddress => {
+                write!(
+                    f,
+                    "Receiving address cannot be confidential without blinding in the tool"
+                )
+            }
+            Error::MissingCovInput => {
+                write!(f, "Pset does not contain any inputs")
+            }
+            Error::Base64(ref e) => write!(f, "Pset must be base64 encoded: {}", e),
+            Error::PsetEncoding(ref e) => write!(f, "Malformed pset: {}", e),
+            Error::PsetErr(ref e) => write!(f, "Pset error: {}", e),
+            Error::FinalizeErr(ref e) => write!(f, "Pset finalize error: {}", e),
+            Error::Rpc(ref e) => write!(f, "{}", e),
+            Error::WrongOutputPosition(out, pos) => {
+                write!(f, "The {} txout must be at output position {}", out, pos)
+            }
+            Error::ConfigFile(ref path, ref e) => {
+                write!(f, "Config file error at {}: {}", path, e)
+            }
+            Error::MalformedConfig(ref e) => write!(f, "Malformed Config file: {}", e),
+            Error::InvalidArg(name, ref value) => {
+                write!(f, "Invalid value \"{}\" for argument {}", value, name)
+            }
+            Error::RegistryFile(ref path, ref e) => {
+                write!(f, "Registry file error at {}: {}", path, e)
+            }
+            Error::MalformedRegistry(ref e) => write!(f, "Malformed registry file: {}", e),
+            Error::UnknownContract(ref id) => write!(f, "No contract with id {} in registry", id),
+            Error::WrongNetwork(ref addr, ref network) => {
+                write!(f, "Address {} is not a valid {} address", addr, network)
+            }
+            Error::IndivisibleAmount(units) => {
+                write!(
+                    f,
+                    "Locked amount and strike must be divisible by the {} option tokens",
+                    units
+                )
+            }
+            Error::TooManyUnits(units, max) => {
+                write!(
+                    f,
+                    "The covenant of {} option tokens exceeds the standard script size, \
+                     use at most {} tokens",
+                    units, max
+                )
+            }
+            Error::MissingFeeOutput => write!(f, "Tx must contain an explicit fee output"),
+            Error::InsufficientFee(fee) => {
+                write!(f, "No output can pay the additional fee of {} sat", fee)
+            }
+            Error::UtxoFile(ref path, ref e) => write!(f, "Utxo file error at {}: {}", path, e),
+            Error::MalformedUtxos(ref e) => write!(f, "Malformed utxo file: {}", e),
+            Error::InvalidUtxo(ref outpoint, reason) => {
+                write!(f, "Cannot spend utxo {}: {}", outpoint, reason)
+            }
+            Error::InsufficientFunds(asset, missing) => {
+                write!(
+                    f,
+                    "Insufficient funds, missing {} sat of asset {}",
+                    missing, asset
+                )
+            }
+            Error::TooFewUtxos(n) => write!(f, "At least {} utxos are required", n),
+            Error::MissingInputSecrets(ref outpoint) => {
+                write!(f, "Missing the blinding factors of input {}", outpoint)
+            }
+            Error::BlindingErr(ref e) => write!(f, "Blinding error: {}", e),
+            Error::MalformedCovenant(reason) => write!(f, "Not an option covenant: {}", reason),
+            Error::InvalidOffer(reason) => write!(f, "Invalid offer: {}", reason),
+            Error::UnverifiedTokens => {
+                write!(f, "Token issuance verification failed")
+            }
+            Error::UnsatisfiedCovenant => {
+                write!(f, "The transaction does not satisfy the covenant")
+            }
+            Error::UnissuedTokens => {
+                write!(
+                    f,
+                    "The opt and bene tokens must be issued as distinct assets, run issue first"
+                )
+            }
+            Error::KeyFile(ref path, ref e) => write!(f, "Keyfile error at {}: {}", path, e),
+            Error::MalformedKeyFile(ref path) => {
+                write!(f, "Keyfile at {} must contain a WIF private key", path)
+            }
+            Error::MissingControlKey => {
+                write!(
+                    f,
+                    "The contract has a private control key, pass --control-keyfile"
+                )
+            }
+            Error::InvalidControlKey => {
+                write!(f, "The keyfile is not the control key of the contract")
+            }
+            Error::MissingSettlement => {
+                write!(f, "Cash-settled options need the attested settlement price")
+            }
+            Error::EuropeanCashSettlement => {
+                write!(
+                    f,
+                    "Cash-settled options settle at expiry and cannot have an exercise window"
+                )
+            }
+            Error::InvalidSettlementPrice(price) => {
+                write!(f, "Price {} is not a settlement price of the option", price)
+            }
+            Error::InvalidOracleSig => write!(f, "Invalid oracle signature of the price"),
+            Error::InvalidExerciseWindow(window) => {
+                write!(f, "Invalid exercise window {} for the expiry", window)
+            }
+            Error::InvalidExerciseUnits(units, remaining) => {
+                write!(
+                    f,
+                    "Cannot exercise {} tokens, {} tokens remaining",
+                    units, remaining
+                )
+            }
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<miniscript::Error> for Error {
+    fn from(e: miniscript::Error) -> Error {
+        Error::MiniscriptErr(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<elements::pset::Error> for Error {
+    fn from(e: elements::pset::Error) -> Error {
+        Error::PsetErr(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<secp256k1_zkp::Error> for Error {
+    fn from(e: secp256k1_zkp::Error) -> Error {
+        Error::BlindingErr(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<rpc::Error> for Error {
+    fn from(e: rpc::Error) -> Error {
+        Error::Rpc(e)
+    }
+}
+
+impl error::Error for Error {}
+
+// Create a txout spending to target spk with `value` amount of asset `asset`
+pub(crate) fn txout(target_spk: Script, value: u64, asset: AssetId) -> TxOut {
+    let mut tx_out = elements::TxOut::default();
+    tx_out.script_pubkey = target_spk;
+    tx_out.value = confidential::Value::Explicit(value);
+    tx_out.asset = confidential::Asset::Explicit(asset);
+    tx_out
+}
+
+// Create a pset output sending `value` of `asset` to `addr`
+pub(crate) fn addr_output(addr: &Address, value: u64, asset: AssetId) -> pset::Output {
+    let mut out = pset::Output::from_txout(txout(addr.script_pubkey(), value, asset));
+    // Set the blinding key so that the output is blinded by walletprocesspsbt
+    out.blinding_key = addr
+        .blinding_pubkey
+        .map(|pk| bitcoin::PublicKey::from_slice(&pk.serialize()).expect("Valid blinding key"));
+    if out.blinding_key.is_some() {
+        out.blinder_index = Some(0);
+    }
+    out
+}
+
+/// Check that the wallet can blind the output of the contract operation `ty` to `addr`.
+/// The claimbene payout to a confidential address must be blinded with
+/// `blinding::blind_pset` instead.
+pub fn check_wallet_blinding(ty: SpendType, addr: &Address) -> Result<(), Error> {
+    // Elements wallet does not lookup the blockchain for explicit txouts for blinding.
+    // Technically, for explicit txouts the wallet can lookup the blockchain and blind
+    // the output. But currently blinding fails for outputs whose asset does
+    // not any input spending input that is IsMine.
+    if ty == SpendType::ClaimBene && addr.blinding_pubkey.is_some() {
+        return Err(Error::ConfidentialAddress);
+    }
+    Ok(())
+}
+
+/// Serialize a pset into base64 encoding
+pub fn pset_to_base64(pset: &Pset) -> String {
+    base64::encode(&serialize(pset))
+}
+
+/// Parse a base64 encoded pset
+pub fn pset_from_base64(s: &str) -> Result<Pset, Error> {
+    let bytes = base64::decode(s).map_err(Error::Base64)?;
+    elements::encode::deserialize(&bytes).map_err(Error::PsetEncoding)
+}
+
+/// Weight of the non-witness part of an input with an empty script sig
+pub(crate) const INPUT_BASE_WEIGHT: usize = (32 + 4 + 1 + 4) * 4;
+/// Weight of the empty issuance rangeproofs and pegin witness of an input
+pub(crate) const INPUT_EMPTY_WITNESS_WEIGHT: usize = 3;
+/// Size of the rangeproof of a blinded output as estimated by the Elements wallet
+const RANGEPROOF_SIZE: usize = 4174;
+/// Maximum number of inputs used in a surjection proof by the Elements wallet
+const SURJECTIONPROOF_MAX_INPUTS: usize = 3;
+
+// Size of the length prefix of a vector
+pub(crate) fn varint_len(n: usize) -> usize {
+    match n {
+        0..=0xfc => 1,
+        0xfd..=0xffff => 3,
+        0x10000..=0xffff_ffff => 5,
+        _ => 9,
+    }
+}
+
+// The increase of the serialized size of an output when it is blinded. Assume that
+// outputs with a blinding key get blinded, the explicit value becomes a commitment
+// and the nonce is set to the ecdh key.
+fn blinding_overhead(out: &pset::Output) -> usize {
+    if out.blinding_key.is_none() {
+        return 0;
+    }
+    match out.to_txout().nonce {
+        confidential::Nonce::Null => (33 - 9) + (33 - 1),
+        _ => 33 - 9,
+    }
+}
+
+/// The weight of the output at `index` after blinding. Rangeproofs and
+/// surjection proofs are estimated like the Elements wallet does.
+pub(crate) fn output_weight(pset: &Pset, index: usize) -> usize {
+    let out = &pset.outputs[index];
+    let base = serialize(&out.to_txout()).len() + blinding_overhead(out);
+    if out.blinding_key.is_none() {
+        // Empty surjection proof and rangeproof
+        return base * 4 + 2;
+    }
+    let n_inputs = pset.inputs.len();
+    let n_used = ::std::cmp::min(n_inputs, SURJECTIONPROOF_MAX_INPUTS);
+    let surjectionproof_len = 2 + (n_inputs + 7) / 8 + 32 * (1 + n_used);
+    base * 4
+        + varint_len(surjectionproof_len)
+        + surjectionproof_len
+        + varint_len(RANGEPROOF_SIZE)
+        + RANGEPROOF_SIZE
+}
+
+// Check if the pset outputs can be used for covenant operations
+fn check_cov_txouts(pset: &Pset) -> Result<(), Error> {
+    let txouts: Vec<TxOut> = pset.outputs.iter().map(|x| x.to_txout()).collect();
+    // Get the serialized size after blinding.
+    let blinding_overhead: usize = pset.outputs.iter().map(blinding_overhead).sum();
+    let ser_txout_len = serialize(&txouts).len() + blinding_overhead;
+    // + 1 for var int len encoding that is not used in sighash calculation
+    if ser_txout_len > 520 + 1 {
+        return Err(Error::CovOutputsTooLarge(ser_txout_len));
+    }
+    Ok(())
+}
+
+// Find the covenant input by the witness script set in addcontract.
+// Fallback to the last input for psets that lost the metadata.
+fn cov_input_index(pset: &Pset, witness_script: &Script) -> Result<usize, Error> {
+    match pset
+        .inputs
+        .iter()
+        .position(|inp| inp.witness_script.as_ref() == Some(witness_script))
+    {
+        Some(i) => Ok(i),
+        None if !pset.inputs.is_empty() => Ok(pset.inputs.len() - 1),
+        None => Err(Error::MissingCovInput),
+    }
+}
+
+// Set the explicit value of the output at `index` keeping the blinding metadata
+pub(crate) fn set_output_value(pset: &mut Pset, index: usize, value: u64) {
+    let out = &mut pset.outputs[index];
+    let mut txout = out.to_txout();
+    txout.value = confidential::Value::Explicit(value);
+    let (blinding_key, blinder_index) = (out.blinding_key, out.blinder_index);
+    *out = pset::Output::from_txout(txout);
+    out.blinding_key = blinding_key;
+    out.blinder_index = blinder_index;
+}
+
+// Move the output equal to `target` to position `index`
+fn move_output(
+    pset: &mut Pset,
+    target: &TxOut,
+    index: usize,
+    name: &'static str,
+) -> Result<(), Error> {
+    let pos = pset
+        .outputs
+        .iter()
+        .position(|x| *target == x.to_txout())
+        .ok_or(Error::MissingCovOutput(name))?;
+    pset.outputs.swap(pos, index);
+    Ok(())
+}
+
+impl OptionContract {
+    /// Create a call option from the config with the given expiry timestamp
+    /// and the strike amount in sats of the claim asset.
+    pub fn from_cfg(cfg: &Config, expiry: u32, strike_amt: u64) -> Self {
+        let locked_asset_params =
+            OptAssetParams::new(cfg.lock_asset, cfg.bene_token, cfg.locked_asset_amount);
+        let claim_asset_params = OptAssetParams::new(cfg.claim_asset, cfg.opt_token, strike_amt);
+
+        OptionContract::new(
+            expiry,
+            locked_asset_params,
+            claim_asset_params,
+            cfg.control_pk,
+            cfg.control_sk,
+            cfg.btc_asset,
+        )
+        .with_units(cfg.opt_token_supply)
+        .with_style(cfg)
+        .with_cfg_settlement(cfg, false)
+    }
+
+    /// Create a put option from the same config as the call option.
+    /// The roles of the assets are swapped: the strike amount of the claim asset
+    /// (usually the stablecoin) is locked as collateral, and the option holder
+    /// sells `locked_asset_amount` of the lock asset at the strike to exercise.
+    pub fn put_from_cfg(cfg: &Config, expiry: u32, strike_amt: u64) -> Self {
+        let locked_asset_params = OptAssetParams::new(cfg.claim_asset, cfg.bene_token, strike_amt);
+        let claim_asset_params =
+            OptAssetParams::new(cfg.lock_asset, cfg.opt_token, cfg.locked_asset_amount);
+
+        OptionContract::new(
+            expiry,
+            locked_asset_params,
+            claim_asset_params,
+            cfg.control_pk,
+            cfg.control_sk,
+            cfg.btc_asset,
+        )
+        .with_units(cfg.opt_token_supply)
+        .with_style(cfg)
+        .with_cfg_settlement(cfg, true)
+    }
+
+    /// Create a new option
+    /// In most cases. Claim Asset would be asset corresponding to usd
+    pub fn new(
+        expiry: u32,
+        locked_asset_params: OptAssetParams,
+        claim_asset_params: OptAssetParams,
+        control_pk: bitcoin::PublicKey,
+        control_sk: Option<bitcoin::PrivateKey>,
+        btc_asset: AssetId,
+    ) -> Self {
+        Self {
+            expiry,
+            locked_asset_params,
+            claim_asset_params,
+            control_key: control_pk,
+            control_sk: control_sk,
+            btc_asset,
+            units: 1,
+            exercise_units: 1,
+            exercise_window: None,
+            cash_settlement: None,
+        }
+    }
+
+    /// Sign the covenant with the secret of a private control key
+    pub fn with_control_sk(mut self, control_sk: PrivateKey) -> Result<Self, Error> {
+        let secp = secp256k1_zkp::Secp256k1::signing_only();
+        if control_sk.public_key(&secp) != self.control_key {
+            return Err(Error::InvalidControlKey);
+        }
+        self.control_sk = Some(control_sk);
+        Ok(self)
+    }
+
+    /// The control key verifying the covenant
+    pub fn control_key(&self) -> PublicKey {
+        self.control_key
+    }
+
+    // Apply the option style of the config
+    fn with_style(self, cfg: &Config) -> Self {
+        match cfg.option_style {
+            OptionStyle::American => self,
+            OptionStyle::European => self.with_exercise_window(cfg.exercise_window),
+        }
+    }
+
+    // Apply the cash settlement of the config, if any
+    fn with_cfg_settlement(self, cfg: &Config, put: bool) -> Self {
+        match cfg.oracle_pk {
+            Some(oracle_pk) => self.with_cash_settlement(CashSettlement::new(
+                oracle_pk,
+                cfg.settlement_prices.clone(),
+                put,
+            )),
+            None => self,
+        }
+    }
+
+    /// Settle the option in cash at the price attested by the oracle instead
+    /// of exercising it by paying the strike
+    pub fn with_cash_settlement(mut self, settlement: CashSettlement) -> Self {
+        self.cash_settlement = Some(settlement);
+        self
+    }
+
+    /// The cash settlement of the option, None if it is physically settled
+    pub fn cash_settlement(&self) -> Option<&CashSettlement> {
+        self.cash_settlement.as_ref()
+    }
+
+    /// The grid price the observed `price` settles at, see `oracle::grid_price`
+    pub fn grid_price(&self, price: u64) -> Result<u64, Error> {
+        let settlement = self
+            .cash_settlement
+            .as_ref()
+            .ok_or(Error::MissingSettlement)?;
+        oracle::grid_price(&settlement.prices, price).ok_or(Error::InvalidSettlementPrice(price))
+    }
+
+    /// Settle the cash-settled option at the grid price of the observed `price`,
+    /// which the oracle attests. The oracle signature is only needed to finalize
+    /// the settlement
+    pub fn with_settlement_price(
+        mut self,
+        price: u64,
+        oracle_sig: Option<secp256k1_zkp::Signature>,
+    ) -> Result<Self, Error> {
+        let price = self.grid_price(price)?;
+        let msg = self.price_message(price)?;
+        {
+            let settlement = self
+                .cash_settlement
+                .as_mut()
+                .ok_or(Error::MissingSettlement)?;
+            if let Some(ref sig) = oracle_sig {
+                if !oracle::verify_price(&settlement.oracle_pk, &msg, sig) {
+                    return Err(Error::InvalidOracleSig);
+                }
+            }
+            settlement.price = Some(price);
+            settlement.oracle_sig = oracle_sig;
+        }
+        Ok(self)
+    }
+
+    /// The message the oracle signs to attest `price` at the expiry of the
+    /// cash-settled option
+    pub fn price_message(&self, price: u64) -> Result<sha256::Hash, Error> {
+        let settlement = self
+            .cash_settlement
+            .as_ref()
+            .ok_or(Error::MissingSettlement)?;
+        // Calls lock the underlying, puts are paid the underlying at exercise
+        let (underlying, quote) = if settlement.put {
+            (&self.claim_asset_params, &self.locked_asset_params)
+        } else {
+            (&self.locked_asset_params, &self.claim_asset_params)
+        };
+        Ok(oracle::price_message(
+            underlying.asset,
+            underlying.value,
+            quote.asset,
+            self.expiry,
+            price,
+        ))
+    }
+
+    /// The amount of the locked asset paid to the opt token holders when
+    /// settling at `price`. The bene token holders can claim the rest
+    pub fn cash_payout(&self, price: u64) -> Result<u64, Error> {
+        let settlement = self
+            .cash_settlement
+            .as_ref()
+            .ok_or(Error::MissingSettlement)?;
+        let locked = self.locked_asset_params.value as u128;
+        let price = price as u128;
+        let payout = if settlement.put {
+            // The strike is locked, pay the amount it exceeds the price
+            locked.saturating_sub(price)
+        } else if price > self.claim_asset_params.value as u128 {
+            // The underlying is locked, pay the share of the price above the strike
+            locked * (price - self.claim_asset_params.value as u128) / price
+        } else {
+            0
+        };
+        Ok(payout as u64)
+    }
+
+    // The attested price the option is settled at
+    fn settled_price(&self) -> Result<u64, Error> {
+        self.cash_settlement
+            .as_ref()
+            .and_then(|settlement| settlement.price)
+            .ok_or(Error::MissingSettlement)
+    }
+
+    /// The lock time from which the bene token holders can claim the collateral
+    /// of the unexercised option. Cash-settled options leave the oracle time
+    /// to attest the price after expiry
+    pub fn expiry_claim_locktime(&self) -> u32 {
+        match self.cash_settlement {
+            Some(_) if self.expiry_is_height() => self.expiry + oracle::ORACLE_TIMEOUT_BLOCKS,
+            Some(_) => self.expiry.saturating_add(oracle::ORACLE_TIMEOUT_SECS),
+            None => self.expiry,
+        }
+    }
+
+    /// Make the option european, exercisable from `window` blocks or seconds
+    /// before expiry. The exercise branches have no upper time bound, so the
+    /// option stays exercisable after expiry until the writer claims the expiry
+    pub fn with_exercise_window(mut self, window: u32) -> Self {
+        self.exercise_window = Some(window);
+        self
+    }
+
+    /// The exercise window of a european option
+    pub fn exercise_window(&self) -> Option<u32> {
+        self.exercise_window
+    }
+
+    /// The lock time from which a european option can be exercised,
+    /// None for american options
+    pub fn exercise_start(&self) -> Result<Option<u32>, Error> {
+        let window = match self.exercise_window {
+            Some(window) => window,
+            None => return Ok(None),
+        };
+        match self.expiry.checked_sub(window) {
+            Some(start)
+                if window > 0 && (start < LOCKTIME_THRESHOLD) == self.expiry_is_height() =>
+            {
+                Ok(Some(start))
+            }
+            _ => Err(Error::InvalidExerciseWindow(window)),
+        }
+    }
+
+    /// Split the contract into `units` option tokens that can be exercised
+    /// separately. The locked amount and the strike must be divisible by `units`
+    pub fn with_units(mut self, units: u64) -> Self {
+        self.units = units;
+        self.exercise_units = units;
+        self
+    }
+
+    /// Exercise, or claim the strike paid at exercise for, `units` tokens
+    /// instead of all the tokens of the contract
+    pub fn with_exercise_units(mut self, units: u64) -> Result<Self, Error> {
+        if units == 0 {
+            return Err(Error::InvalidExerciseUnits(units, self.units));
+        }
+        self.exercise_units = units;
+        Ok(self)
+    }
+
+    /// The contract locking the collateral of the `units` tokens remaining
+    /// after exercising the other tokens
+    pub fn successor(&self, units: u64) -> Result<Self, Error> {
+        if units == 0 || units > self.units {
+            return Err(Error::InvalidExerciseUnits(
+                self.units.saturating_sub(units),
+                self.units,
+            ));
+        }
+        let (locked_per_unit, strike_per_unit) = self.per_unit()?;
+        let mut contract = self.clone();
+        contract.locked_asset_params.value = locked_per_unit * units;
+        contract.claim_asset_params.value = strike_per_unit * units;
+        Ok(contract.with_units(units))
+    }
+
+    /// The number of option tokens covering the locked asset
+    pub fn units(&self) -> u64 {
+        self.units
+    }
+
+    /// The number of tokens exercised or claimed
+    pub fn exercise_units(&self) -> u64 {
+        self.exercise_units
+    }
+
+    /// The locked amount and the strike of a single token
+    fn per_unit(&self) -> Result<(u64, u64), Error> {
+        let locked = self.locked_asset_params.value;
+        let strike = self.claim_asset_params.value;
+        if self.units == 0 || locked % self.units != 0 || strike % self.units != 0 {
+            return Err(Error::IndivisibleAmount(self.units));
+        }
+        Ok((locked / self.units, strike / self.units))
+    }
+
+    /// The maximum number of tokens of the contract whose covenant fits in a
+    /// standard witness script. Each token after the first adds a partial exercise
+    /// branch of the same size, so the limit is measured from the witness scripts
+    /// of the covenants of one and two tokens with the terms of the contract.
+    pub fn max_units(&self) -> Result<u64, Error> {
+        // The covenant of `units` tokens with one satoshi locked per token, the
+        // amounts are explicit values of a fixed size
+        let template = |units: u64, successor_spks: &[Script]| {
+            let mut contract = self.clone().with_units(units);
+            contract.cash_settlement = None;
+            contract.locked_asset_params.value = units;
+            contract.claim_asset_params.value = units;
+            contract.deposit_desc_with(successor_spks)
+        };
+        let single = template(1, &[])?;
+        let one = single.explicit_script().len();
+        let two = template(2, &[single.script_pubkey()])?
+            .explicit_script()
+            .len();
+        let partial = two - one;
+        if one > MAX_STANDARD_P2WSH_SCRIPT_SIZE {
+            return Ok(0);
+        }
+        Ok(((MAX_STANDARD_P2WSH_SCRIPT_SIZE - one) / partial) as u64 + 1)
+    }
+
+    /// Check that the covenant of the tokens can be standard before building it,
+    /// the size of the covenant grows with the number of tokens
+    fn check_units(&self) -> Result<(), Error> {
+        let max_units = self.max_units()?;
+        if self.units > max_units {
+            return Err(Error::TooManyUnits(self.units, max_units));
+        }
+        Ok(())
+    }
+
+    /// Check that the opt and bene tokens are issued. The tokens of a config
+    /// created by init are zero until they are issued, and a covenant with
+    /// the same opt and bene token could be cancelled by burning either token.
+    fn check_tokens(&self) -> Result<(), Error> {
+        let unissued = AssetId::from_slice(&[0u8; 32]).expect("32 byte asset id");
+        let opt_tkn = self.claim_asset_params.opt_tkn;
+        let bene_tkn = self.locked_asset_params.opt_tkn;
+        if opt_tkn == unissued || bene_tkn == unissued || opt_tkn == bene_tkn {
+            return Err(Error::UnissuedTokens);
+        }
+        Ok(())
+    }
+
+    /// The number of tokens exercised, checked against the remaining tokens
+    fn checked_exercise_units(&self) -> Result<u64, Error> {
+        // Cash-settled options are settled for all the tokens at once
+        let partial_cash = self.cash_settlement.is_some() && self.exercise_units != self.units;
+        if self.exercise_units > self.units || partial_cash {
+            return Err(Error::InvalidExerciseUnits(self.exercise_units, self.units));
+        }
+        Ok(self.exercise_units)
+    }
+
+    /// The expiry of the option
+    pub fn expiry(&self) -> u32 {
+        self.expiry
+    }
+
+    /// The asset paying the transaction fees
+    pub fn btc_asset(&self) -> AssetId {
+        self.btc_asset
+    }
+
+    /// Whether the expiry is a block height instead of a timestamp
+    pub fn expiry_is_height(&self) -> bool {
+        self.expiry < LOCKTIME_THRESHOLD
+    }
+
+    /// The asset locked by the writer
+    pub fn locked_asset_params(&self) -> &OptAssetParams {
+        &self.locked_asset_params
+    }
+
+    /// The asset paid by the buyer at exercise
+    pub fn claim_asset_params(&self) -> &OptAssetParams {
+        &self.claim_asset_params
+    }
+
+    // TxOut which burns `units` option tokens
+    fn burn_opt(&self, units: u64) -> TxOut {
+        let mut tx_out = TxOut::default();
+        tx_out.asset = confidential::Asset::Explicit(self.claim_asset_params.opt_tkn);
+        tx_out.value = confidential::Value::Explicit(units);
+        // Should we put some message?
+        tx_out.script_pubkey = Script::new_op_return(&[]);
+        tx_out
+    }
+
+    /// Returns a txout that burns `units` bene tokens.
+    fn burn_bene(&self, units: u64) -> TxOut {
+        let mut tx_out = TxOut::default();
+        tx_out.asset = confidential::Asset::Explicit(self.locked_asset_params.opt_tkn);
+        tx_out.value = confidential::Value::Explicit(units);
+        // Should we put some message?
+        tx_out.script_pubkey = Script::new_op_return(&[]);
+        tx_out
+    }
+
+    /// Returns a txout that burns `units` bene tokens to claim the strike paid
+    /// by exercising them. The OP_RETURN commits to the number of tokens remaining
+    /// after the exercise, which is different for each exercise of the contract,
+    /// so that one burn cannot claim the payouts of several exercises.
+    fn burn_bene_payout(&self, units: u64) -> TxOut {
+        let mut tx_out = self.burn_bene(units);
+        let remaining = self.units.saturating_sub(units);
+        tx_out.script_pubkey = Script::new_op_return(&serialize(&remaining));
+        tx_out
+    }
+
+    /// Create an wsh address which fixes the first output to the following
+    /// Explicit Amount = units; Asset = <explicit_asset>; Nonce= Null;
+    /// ScriptPubkey = OP_RETURN <remaining tokens>
+    /// Returns the wsh script pubkey and the corresponding txout
+    fn burn_bene_desc(
+        &self,
+        units: u64,
+    ) -> Result<(CovenantDescriptor<bitcoin::PublicKey>, TxOut), Error> {
+        self.check_tokens()?;
+        let tx_out = self.burn_bene_payout(units);
+        let inner_ms = format!("outputs_pref({})", &serialize_hex(&tx_out));
+        let ms = Miniscript::<PublicKey, Segwitv0>::from_str_insane(&inner_ms)?;
+        let desc = CovenantDescriptor::new(self.control_key, ms)?;
+        Ok((desc, tx_out))
+    }
+
+    /// The covenant descriptor of the output paying the strike of `units` tokens
+    /// at exercise, which is spent by claimbene. Each exercise of the contract,
+    /// leaving a different number of tokens, has its own payout covenant
+    pub fn exercise_payout_desc(
+        &self,
+        units: u64,
+    ) -> Result<CovenantDescriptor<bitcoin::PublicKey>, Error> {
+        self.burn_bene_desc(units).map(|(desc, _)| desc)
+    }
+
+    /// Returns a txout that burns `units` bene tokens.
+    /// p2wsh wrapped op-return. Elements does not allow
+    /// multi-op return per transaction
+    fn burn_bene_wsh(&self, units: u64) -> TxOut {
+        let mut tx_out = TxOut::default();
+        tx_out.asset = confidential::Asset::Explicit(self.locked_asset_params.opt_tkn);
+        tx_out.value = confidential::Value::Explicit(units);
+        // Should we put some message?
+        tx_out.script_pubkey = Script::new_op_return(&[]).to_v0_p2wsh();
+        tx_out
+    }
+
+    // Returns a pair of txouts for exercising `units` tokens. The first txout pays the strike
+    // of the tokens and has a covenant constraint that it can only be spend by transaction
+    // whose output at index 0 is the second txout.
+    // Cash-settled options pay the share of the collateral at the settled price instead.
+    fn exercise_txout(&self, units: u64) -> Result<(TxOut, TxOut), Error> {
+        if self.cash_settlement.is_some() {
+            return self.settlement_txout(self.settled_price()?);
+        }
+        let (_, strike_per_unit) = self.per_unit()?;
+        let (desc, txout2) = self.burn_bene_desc(units)?;
+        let exercise_txout = txout(
+            desc.script_pubkey(),
+            strike_per_unit * units,
+            self.claim_asset_params.asset,
+        );
+        Ok((exercise_txout, txout2))
+    }
+
+    // Returns a pair of txouts for settling in cash at `price`. The first txout keeps
+    // the share of the collateral of the bene token holders, which is spent like
+    // the exercise txout.
+    fn settlement_txout(&self, price: u64) -> Result<(TxOut, TxOut), Error> {
+        let (desc, txout2) = self.burn_bene_desc(self.units)?;
+        let settlement_txout = txout(
+            desc.script_pubkey(),
+            self.locked_asset_params.value - self.cash_payout(price)?,
+            self.locked_asset_params.asset,
+        );
+        Ok((settlement_txout, txout2))
+    }
+
+    /// The txout re-locking the collateral of the `units` tokens remaining
+    /// after a partial exercise in the successor covenant with script pubkey `spk`
+    fn successor_txout(&self, units: u64, spk: Script) -> Result<TxOut, Error> {
+        let successor = self.successor(units)?;
+        Ok(txout(
+            spk,
+            successor.locked_asset_params.value,
+            successor.locked_asset_params.asset,
+        ))
+    }
+
+    /// Helper function to create a descriptor
+    pub fn deposit_desc(&self) -> Result<Descriptor<PublicKey>, Error> {
+        if self.cash_settlement.is_some() {
+            // Cash-settled options have no partial exercises
+            return self.deposit_desc_with(&[]);
+        }
+        self.per_unit()?;
+        let successor_spks = self.successor_spks(self.units - 1)?;
+        self.deposit_desc_with(&successor_spks)
+    }
+
+    /// The script pubkeys of the successor covenants locking 1 to `units` tokens,
+    /// where the i-th script pubkey locks `i + 1` tokens. The covenant of each
+    /// successor commits to the covenants of its own successors, build them from
+    /// the smallest to avoid recomputing them.
+    fn successor_spks(&self, units: u64) -> Result<Vec<Script>, Error> {
+        self.check_units()?;
+        let mut successor_spks = Vec::new();
+        for units in 1..=units {
+            let desc = self.successor(units)?.deposit_desc_with(&successor_spks)?;
+            successor_spks.push(desc.script_pubkey());
+        }
+        Ok(successor_spks)
+    }
+
+    /// Create the descriptor given the script pubkeys of the successor
+    /// covenants, where `successor_spks[i]` locks `i + 1` tokens
+    fn deposit_desc_with(&self, successor_spks: &[Script]) -> Result<Descriptor<PublicKey>, Error> {
+        self.check_tokens()?;
+        self.per_unit()?;
+        if let Some(ref settlement) = self.cash_settlement {
+            // The settlement branches are not gated by the exercise window
+            if self.exercise_window.is_some() {
+                return Err(Error::EuropeanCashSettlement);
+            }
+            let mut grid = vec![];
+            for &price in &settlement.prices {
+                let bene_amount = self.locked_asset_params.value - self.cash_payout(price)?;
+                grid.push((self.price_message(price)?, bene_amount));
+            }
+            return self.settlement_desc(&grid);
+        }
+        // TxOut which burns the option
+        let burn_opt_txout = self.burn_opt(self.units);
+
+        // Create the three conditions of spending the output
+        // As B fragments of Miniscript
+        let (expiry_cond, cancel_cond) = self.expiry_cancel_conds();
+
+        // 3. Exercise Condition
+        // The first output should burn the opt token and the second out
+        // should send the claim asset amount to the benefit token holder
+        let (exercise_txout, _) = self.exercise_txout(self.units)?;
+        let exercise_start = self.exercise_start()?;
+        let exercise_cond = exercise_cond(
+            exercise_start,
+            &format!(
+                "{}{}",
+                &serialize_hex(&burn_opt_txout),
+                &serialize_hex(&exercise_txout)
+            ),
+        );
+
+        // 4. Partial exercise conditions for contracts with multiple tokens
+        // Exercising k tokens burns k opt tokens, pays k/units of the strike and
+        // the third output re-locks the remaining collateral in the successor covenant
+        let mut partial_conds = String::new();
+        for k in 1..self.units {
+            let (exercise_txout, _) = self.exercise_txout(k)?;
+            let outputs = format!(
+                "{}{}{}",
+                &serialize_hex(&self.burn_opt(k)),
+                &serialize_hex(&exercise_txout),
+                &serialize_hex(&self.successor_txout(
+                    self.units - k,
+                    successor_spks[(self.units - k - 1) as usize].clone()
+                )?),
+            );
+            partial_conds.push(',');
+            partial_conds.push_str(&exercise_cond(exercise_start, &outputs));
+        }
+
+        // Combine all the conditions using a thresh
+
+        let inner_ms = format!(
+            "thresh(1,{},{},{}{})",
+            expiry_cond, cancel_cond, exercise_cond, partial_conds
+        );
+        let desc = Descriptor::<PublicKey>::from_str(&format!(
+            "elcovwsh({},{})",
+            self.control_key, inner_ms
+        ))?;
+        Ok(desc)
+    }
+
+    // The expiry and cancel conditions of the covenant, shared by physically
+    // and cash-settled options
+    fn expiry_cancel_conds(&self) -> (String, String) {
+        let burn_opt_txout = self.burn_opt(self.units);
+        let burn_bene_txout = self.burn_bene(self.units);
+        let burn_bene_wsh_txout = self.burn_bene_wsh(self.units); // a wsh wrapped op-return
+
+        // 1. Create the expiry condition String
+        // Burn the bene token to claim the expired option
+        let expiry_cond = format!(
+            "l:and_b(n:after({}),atv:outputs_pref({}))",
+            self.expiry_claim_locktime(),
+            serialize_hex(&burn_bene_txout),
+        );
+
+        // 2. Cancel the trade, burn both the token and free the coins
+        // First output is burning opt, second one burns bene
+        let cancel_cond = format!(
+            "altv:outputs_pref({}{})",
+            &serialize_hex(&burn_opt_txout),
+            &serialize_hex(&burn_bene_wsh_txout),
+        );
+        (expiry_cond, cancel_cond)
+    }
+
+    /// The covenant descriptor of the cash-settled option, given the price
+    /// message and the share of the bene token holders of each settlement branch.
+    /// The covenant only commits to the prices through the price messages.
+    pub(crate) fn settlement_desc(
+        &self,
+        grid: &[(sha256::Hash, u64)],
+    ) -> Result<Descriptor<PublicKey>, Error> {
+        let settlement = self
+            .cash_settlement
+            .as_ref()
+            .ok_or(Error::MissingSettlement)?;
+        let burn_opt_txout = self.burn_opt(self.units);
+        let (expiry_cond, cancel_cond) = self.expiry_cancel_conds();
+
+        // 3. Settlement conditions, one for each settlement price
+        // The oracle signature of the price unlocks the branch from expiry, the first
+        // output burns the opt token and the second keeps the share of the bene token holder
+        let (payout_desc, _) = self.burn_bene_desc(self.units)?;
+        let mut settlement_conds = vec![];
+        for &(msg, bene_amount) in grid {
+            let settlement_txout = txout(
+                payout_desc.script_pubkey(),
+                bene_amount,
+                self.locked_asset_params.asset,
+            );
+            settlement_conds.push(format!(
+                "al:and_b(n:after({}),a:and_b(csfs({},{}),atv:outputs_pref({}{})))",
+                self.expiry,
+                settlement.oracle_pk,
+                msg,
+                &serialize_hex(&burn_opt_txout),
+                &serialize_hex(&settlement_txout),
+            ));
+        }
+        let inner_ms = format!(
+            "thresh(1,{},{},{})",
+            expiry_cond,
+            cancel_cond,
+            settlement_conds.join(",")
+        );
+        let desc = Descriptor::<PublicKey>::from_str(&format!(
+            "elcovwsh({},{})",
+            self.control_key, inner_ms
+        ))?;
+        Ok(desc)
+    }
+
+    /// The deterministic ID of the contract. This is the sha256 of the deposit
+    /// script pubkey, which commits to all the terms of the option
+    pub fn contract_id(&self) -> Result<sha256::Hash, Error> {
+        let spk = self.deposit_desc()?.script_pubkey();
+        Ok(sha256::Hash::hash(spk.as_bytes()))
+    }
+
+    /// Get an address to deposit coins for this option
+    /// Can error if the underlying covenant creation fails
+    /// because of resource limits
+    pub fn deposit_addr(
+        &self,
+        addr_params: &'static AddressParams,
+    ) -> Result<elements::Address, Error> {
+        let desc = self.deposit_desc()?;
+        let addr = desc.address(addr_params)?;
+        Ok(addr)
+    }
+
+    /// Returns the covenant descriptor being spent along with the witness utxo
+    /// for the given type of contract operation.
+    /// In expiry, cancel and exercise cases we are spending from covenant prevout
+    /// But in claimbene case, we are spending the exercise transaction
+    pub(crate) fn spent_cov(
+        &self,
+        ty: SpendType,
+    ) -> Result<(CovenantDescriptor<PublicKey>, TxOut), Error> {
+        match ty {
+            SpendType::Expiry | SpendType::Cancel | SpendType::Exercise => {
+                // The descriptor must be a covenant descriptor
+                let desc = self.deposit_desc()?;
+                let desc = desc.as_cov()?.clone();
+                let utxo = txout(
+                    desc.script_pubkey(),
+                    self.locked_asset_params.value,
+                    self.locked_asset_params.asset,
+                );
+                Ok((desc, utxo))
+            }
+            SpendType::ClaimBene => {
+                let (exercise_txout, _) = self.exercise_txout(self.exercise_units)?;
+                let (desc, _) = self.burn_bene_desc(self.exercise_units)?;
+                Ok((desc, exercise_txout))
+            }
+        }
+    }
+
+    /// The lock time required by the `after()` fragment of the covenant branch
+    /// of the contract operation `ty`, if any
+    // The lock time from which the option can be exercised: the start of the window
+    // of european options and the expiry of cash-settled options, which settle
+    // at the price attested at expiry
+    fn exercise_locktime(&self) -> Result<Option<u32>, Error> {
+        match self.cash_settlement {
+            Some(_) => Ok(Some(self.expiry)),
+            None => self.exercise_start(),
+        }
+    }
+
+    pub(crate) fn required_locktime(&self, ty: SpendType) -> Result<Option<u32>, Error> {
+        Ok(match ty {
+            SpendType::Expiry => Some(self.expiry_claim_locktime()),
+            SpendType::Exercise => self.exercise_locktime()?,
+            SpendType::Cancel | SpendType::ClaimBene => None,
+        })
+    }
+
+    /// Adds the contract prevout as an input along with the covenant metadata
+    /// (witness utxo, witness script and sighash type), and an output sending
+    /// `value` of `asset` to `addr`.
+    fn add_contract_io(
+        &self,
+        pset: &mut Pset,
+        contract_prevout: OutPoint,
+        addr: Address,
+        ty: SpendType,
+        value: u64,
+        asset: AssetId,
+    ) -> Result<(), Error> {
+        let (desc, utxo) = self.spent_cov(ty)?;
+        let mut inp = pset::Input::from_prevout(contract_prevout);
+        inp.sequence = Some(0);
+        inp.witness_utxo = Some(utxo);
+        inp.witness_script = Some(desc.explicit_script());
+        inp.sighash_type = Some(SigHashType::All);
+        if let Some(locktime) = self.required_locktime(ty)? {
+            if self.expiry_is_height() {
+                inp.required_height_locktime = Some(locktime);
+            } else {
+                inp.required_time_locktime = Some(locktime);
+            }
+        }
+
+        pset.add_input(inp);
+        pset.add_output(addr_output(&addr, value, asset));
+        Ok(())
+    }
+
+    /// Returns the partial transaction for the contract operation which must be
+    /// funded by the wallet before adding the contract
+    pub fn partial_tx(&self, ty: SpendType) -> Result<Transaction, Error> {
+        match ty {
+            SpendType::Exercise => self.exercise_opt(),
+            SpendType::Cancel => Ok(self.cancel_opt()),
+            SpendType::Expiry => Ok(self.claim_expiry()),
+            SpendType::ClaimBene => Ok(self.claim_bene()),
+        }
+    }
+
+    /// Add the contract input and output to the funded pset for the contract
+    /// operation `ty`
+    pub fn add_contract(
+        &self,
+        pset: &mut Pset,
+        prevout: OutPoint,
+        addr: Address,
+        ty: SpendType,
+    ) -> Result<(), Error> {
+        match ty {
+            SpendType::Cancel => self.cancel_tx2(pset, prevout, addr),
+            SpendType::Expiry => self.claim_expiry_tx2(pset, prevout, addr),
+            SpendType::Exercise => self.exercise_opt_tx2(pset, prevout, addr),
+            SpendType::ClaimBene => self.claim_bene_tx2(pset, prevout, addr),
+        }
+    }
+
+    /// Remove the locked asset from the contract
+    pub fn claim_expiry(&self) -> Transaction {
+        let out = self.burn_bene(self.units);
+        Transaction {
+            version: 2,
+            // after() is satisfied by any lock_time of the same type at or past the expiry
+            lock_time: self.expiry_claim_locktime(),
+            input: vec![],
+            output: vec![out],
+        }
+    }
+
+    /// Second transaction for claiming expiry
+    pub fn claim_expiry_tx2(
+        &self,
+        pset: &mut Pset,
+        contract_prevout: OutPoint,
+        addr: Address,
+    ) -> Result<(), Error> {
+        self.add_contract_io(
+            pset,
+            contract_prevout,
+            addr,
+            SpendType::Expiry,
+            self.locked_asset_params.value,
+            self.locked_asset_params.asset,
+        )?;
+
+        // Need to re-arrange all outputs for covenant creation
+        move_output(pset, &self.burn_bene(self.units), 0, "burn")?;
+        check_cov_txouts(pset)
+    }
+
+    /// The first transaction to send when cancelling the option
+    pub fn cancel_opt(&self) -> Transaction {
+        // Fill in a transaction with 0 inputs and 2 outputs
+        // Input: None
+        // Outputs: 1) The burned opt token
+        //          2) The burned bene token wrapped in wsh
+
+        let out1 = self.burn_opt(self.units);
+        let out2 = self.burn_bene_wsh(self.units);
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![out1, out2],
+        };
+        tx
+    }
+
+    /// Takes in the funded pset and adds contract prevout and an output
+    /// to claim the locked asset
+    pub fn cancel_tx2(
+        &self,
+        pset: &mut Pset,
+        contract_prevout: OutPoint,
+        addr: Address,
+    ) -> Result<(), Error> {
+        self.add_contract_io(
+            pset,
+            contract_prevout,
+            addr,
+            SpendType::Cancel,
+            self.locked_asset_params.value,
+            self.locked_asset_params.asset,
+        )?;
+
+        // Need to re-arrange all outputs for covenant creation
+        move_output(pset, &self.burn_opt(self.units), 0, "burn")?;
+        move_output(
+            pset,
+            &self.burn_bene_wsh(self.units),
+            1,
+            "burn wsh wrapped op-return bene token",
+        )?;
+        check_cov_txouts(pset)
+    }
+
+    /// Incase the option is exercised, claim the corresponding usd amount
+    /// Creates a raw transaction that burns the bene token
+    pub fn claim_bene(&self) -> Transaction {
+        // claiming using bene token is the same as expiry except that we claim using
+        // different asset, burn only the exercised tokens and we don't set a locktime
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![self.burn_bene_payout(self.exercise_units)],
+        }
+    }
+
+    /// Incase the option is exercised, claim the corresponding usd amount
+    /// Creates a raw transaction that burns the bene token
+    pub fn claim_bene_tx2(
+        &self,
+        pset: &mut Pset,
+        prevout: OutPoint,
+        addr: Address,
+    ) -> Result<(), Error> {
+        // claiming using bene token is the same as expiry except that we claim using
+        // different asset
+        let (exercise_txout, _) = self.exercise_txout(self.exercise_units)?;
+        let (value, asset) = match (exercise_txout.value, exercise_txout.asset) {
+            (confidential::Value::Explicit(value), confidential::Asset::Explicit(asset)) => {
+                (value, asset)
+            }
+            _ => unreachable!("Exercise txouts are explicit"),
+        };
+        self.add_contract_io(pset, prevout, addr, SpendType::ClaimBene, value, asset)?;
+
+        move_output(pset, &self.burn_bene_payout(self.exercise_units), 0, "burn")?;
+        check_cov_txouts(pset)
+    }
+
+    /// Get the transaction to broadcast at exercise
+    /// Forward this transaction to elementsd for fundrawtransaction
+    pub fn exercise_opt_tx2(
+        &self,
+        pset: &mut Pset,
+        contract_prevout: OutPoint,
+        addr: Address,
+    ) -> Result<(), Error> {
+        let units = self.checked_exercise_units()?;
+        let (locked_per_unit, _) = self.per_unit()?;
+        // Cash-settled options pay the share of the opt token holders at the settled price
+        let value = match self.cash_settlement {
+            Some(_) => self.cash_payout(self.settled_price()?)?,
+            None => locked_per_unit * units,
+        };
+        self.add_contract_io(
+            pset,
+            contract_prevout,
+            addr,
+            SpendType::Exercise,
+            value,
+            self.locked_asset_params.asset,
+        )?;
+        // Re-lock the collateral of the remaining tokens
+        let successor_txout = self.exercise_successor_txout()?;
+        if let Some(ref successor_txout) = successor_txout {
+            pset.add_output(pset::Output::from_txout(successor_txout.clone()));
+        }
+
+        // Need to re-arrange all outputs for covenant creation
+        move_output(pset, &self.burn_opt(units), 0, "burn")?;
+        move_output(
+            pset,
+            &self.exercise_txout(units)?.0,
+            EXERCISE_PAYOUT_VOUT as usize,
+            "exercise",
+        )?;
+        if let Some(ref successor_txout) = successor_txout {
+            move_output(pset, successor_txout, SUCCESSOR_VOUT as usize, "successor")?;
+        }
+        check_cov_txouts(pset)
+    }
+
+    /// The txout re-locking the remaining collateral in the successor covenant
+    /// when exercising only part of the tokens
+    fn exercise_successor_txout(&self) -> Result<Option<TxOut>, Error> {
+        let remaining = self.units - self.checked_exercise_units()?;
+        if remaining == 0 {
+            return Ok(None);
+        }
+        let mut successor_spks = self.successor_spks(remaining)?;
+        let spk = successor_spks.pop().expect("Remaining tokens");
+        Ok(Some(self.successor_txout(remaining, spk)?))
+    }
+
+    /// The outputs that the covenant requires at the start of the spending
+    /// transaction for the contract operation `ty` along with their names
+    pub(crate) fn cov_outputs(&self, ty: SpendType) -> Result<Vec<(TxOut, &'static str)>, Error> {
+        Ok(match ty {
+            SpendType::Exercise => {
+                let units = self.checked_exercise_units()?;
+                let mut outs = vec![
+                    (self.burn_opt(units), "burn"),
+                    (self.exercise_txout(units)?.0, "exercise"),
+                ];
+                if let Some(successor_txout) = self.exercise_successor_txout()? {
+                    outs.push((successor_txout, "successor"));
+                }
+                outs
+            }
+            SpendType::Cancel => vec![
+                (self.burn_opt(self.units), "burn"),
+                (
+                    self.burn_bene_wsh(self.units),
+                    "burn wsh wrapped op-return bene token",
+                ),
+            ],
+            SpendType::Expiry => vec![(self.burn_bene(self.units), "burn")],
+            SpendType::ClaimBene => vec![(self.burn_bene_payout(self.exercise_units), "burn")],
+        })
+    }
+
+    /// Check that the outputs required by the covenant are at the right position
+    /// Wallets processing the pset must not re-order the outputs
+    fn check_cov_positions(&self, pset: &Pset, ty: SpendType) -> Result<(), Error> {
+        for (i, (txout, name)) in self.cov_outputs(ty)?.into_iter().enumerate() {
+            let pos = pset
+                .outputs
+                .iter()
+                .position(|x| txout == x.to_txout())
+                .ok_or(Error::MissingCovOutput(name))?;
+            if pos != i {
+                return Err(Error::WrongOutputPosition(name, i));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sign the covenant input of the signed pset and finalize it.
+    /// All other inputs must already be signed by the wallet
+    pub fn finalize_tx(&self, pset: &mut Pset, ty: SpendType) -> Result<(), Error> {
+        self.check_cov_positions(pset, ty)?;
+        // Miniscript Magic
+        let (desc, utxo) = self.spent_cov(ty)?;
+        let witness_script = desc.explicit_script();
+
+        let cov_index = cov_input_index(pset, &witness_script)?;
+
+        let secp = elements::secp256k1_zkp::Secp256k1::new();
+        let tx = pset.extract_tx()?;
+        let cov_in = &mut pset.inputs[cov_index];
+        cov_in.witness_script = Some(witness_script);
+        cov_in.witness_utxo = Some(utxo.clone());
+
+        // Create a signature for covenant operation
+        let mut sighash_cache = elements::sighash::SigHashCache::new(&tx);
+        let sighash_type = cov_in.sighash_type.unwrap_or(SigHashType::All);
+        let sighash_msg = sighash_cache.segwitv0_sighash(
+            cov_index,
+            &desc.cov_script_code(),
+            utxo.value,
+            sighash_type,
+        );
+
+        let msg = secp256k1_zkp::Message::from_slice(&sighash_msg[..]).expect("32 byte sighash");
+        let control_sk = self.control_sk.as_ref().ok_or(Error::MissingControlKey)?;
+        let sig = secp.sign(&msg, &control_sk.key);
+
+        let rawsig = miniscript::elementssig_to_rawsig(&(sig, sighash_type));
+        cov_in.partial_sigs.insert(self.control_key, rawsig);
+
+        let settlement = match (ty, self.cash_settlement.as_ref()) {
+            (SpendType::Exercise, Some(settlement)) => settlement,
+            _ => {
+                miniscript::pset::finalize_input(pset, &secp, cov_index)
+                    .map_err(Error::FinalizeErr)?;
+                return Ok(());
+            }
+        };
+        // The csfs fragment of the settlement branch is satisfied by the oracle
+        // signature, the control signature is looked up in the pset input
+        let oracle = OracleSatisfier {
+            oracle_pk: settlement.oracle_pk,
+            msg: self.price_message(self.settled_price()?)?,
+            sig: settlement.oracle_sig.ok_or(Error::MissingSettlement)?,
+        };
+        let (witness, _) =
+            desc.get_satisfaction((PsetInputSatisfier::new(pset, cov_index), oracle))?;
+        let cov_in = &mut pset.inputs[cov_index];
+        cov_in.final_script_witness = Some(witness);
+        cov_in.partial_sigs.clear();
+        Ok(())
+    }
+
+    /// The weight of the covenant input of the pset after it is finalized.
+    /// Finalizes a copy of the pset, so the wallet inputs need not be signed.
+    /// Outputs with a blinding key are assumed to be blinded by the wallet.
+    pub fn cov_input_weight(&self, pset: &Pset, ty: SpendType) -> Result<usize, Error> {
+        let mut dry_run = pset.clone();
+        self.finalize_tx(&mut dry_run, ty)?;
+        let (desc, _) = self.spent_cov(ty)?;
+        let cov_index = cov_input_index(&dry_run, &desc.explicit_script())?;
+        let witness = dry_run.inputs[cov_index]
+            .final_script_witness
+            .clone()
+            .unwrap_or_default();
+        let witness_len = varint_len(witness.len())
+            + witness
+                .iter()
+                .map(|w| varint_len(w.len()) + w.len())
+                .sum::<usize>();
+        // The covenant witness contains the serialized outputs which grow when blinded
+        let blinding_overhead: usize = pset.outputs.iter().map(blinding_overhead).sum();
+        Ok(INPUT_BASE_WEIGHT + INPUT_EMPTY_WITNESS_WEIGHT + witness_len + blinding_overhead)
+    }
+
+    /// Add the contract to the funded pset and pay the fee for the contract input
+    /// and output at `fee_rate` sat/vB. The wallet must have funded the partial tx
+    /// at the same fee rate, the returned additional fee is added to the fee output.
+    /// It is deducted from the contract output if it pays the fee asset, otherwise
+    /// from the largest fee asset output that is not constrained by the covenant.
+    pub fn add_contract_with_fee(
+        &self,
+        pset: &mut Pset,
+        prevout: OutPoint,
+        addr: Address,
+        ty: SpendType,
+        fee_rate: f64,
+    ) -> Result<u64, Error> {
+        let spk = addr.script_pubkey();
+        self.add_contract(pset, prevout, addr, ty)?;
+        let out_index = pset
+            .outputs
+            .iter()
+            .rposition(|out| out.to_txout().script_pubkey == spk)
+            .ok_or(Error::MissingCovOutput("contract"))?;
+
+        let weight = self.cov_input_weight(pset, ty)? + output_weight(pset, out_index);
+        let fee = (weight as f64 / 4.0 * fee_rate).ceil() as u64;
+
+        let btc = confidential::Asset::Explicit(self.btc_asset);
+        let explicit_value = |out: &pset::Output| match out.to_txout().value {
+            confidential::Value::Explicit(v) => Some(v),
+            _ => None,
+        };
+        let fee_index = pset
+            .outputs
+            .iter()
+            .position(|out| {
+                let txout = out.to_txout();
+                txout.script_pubkey.is_empty() && txout.asset == btc
+            })
+            .ok_or(Error::MissingFeeOutput)?;
+        let n_cov = self.cov_outputs(ty)?.len();
+        let payer = pset
+            .outputs
+            .iter()
+            .enumerate()
+            .skip(n_cov)
+            .filter(|&(i, out)| i != fee_index && out.to_txout().asset == btc)
+            .filter_map(|(i, out)| explicit_value(out).map(|v| (i, v)))
+            .filter(|&(_, v)| v > fee)
+            .max_by_key(|&(i, v)| (i == out_index, v))
+            .ok_or(Error::InsufficientFee(fee))?;
+
+        let fee_value = explicit_value(&pset.outputs[fee_index]).ok_or(Error::MissingFeeOutput)?;
+        set_output_value(pset, payer.0, payer.1 - fee);
+        set_output_value(pset, fee_index, fee_value + fee);
+        Ok(fee)
+    }
+
+    /// Get the transaction to broadcast at exercise
+    /// Forward this transaction to elementsd for fundrawtransaction
+    pub fn exercise_opt(&self) -> Result<Transaction, Error> {
+        // Fill in a transaction with 0 inputs and 2 outputs
+        // Input: None
+        // Outputs: 1) The burned opt token
+        //          2) The btc amount sent to requested address
+
+        let units = self.checked_exercise_units()?;
+        let out1 = self.burn_opt(units);
+        let (out2, _) = self.exercise_txout(units)?;
+        let tx = Transaction {
+            version: 2,
+            // European options can only be exercised from the start of the window,
+            // cash-settled options from expiry
+            lock_time: self.exercise_locktime()?.unwrap_or(0),
+            input: vec![],
+            output: vec![out1, out2],
+        };
+        Ok(tx)
+    }
+}
+
+/// A contract with fixed assets for tests
+#[cfg(test)]
+pub fn test_contract() -> OptionContract {
+    use config::{BTC_ASSET, CTRL_PK, CTRL_SK};
+
+    let btc = AssetId::from_slice(&BTC_ASSET).unwrap();
+    let usd = AssetId::from_slice(&[1u8; 32]).unwrap();
+    let opt_tkn = AssetId::from_slice(&[2u8; 32]).unwrap();
+    let bene_tkn = AssetId::from_slice(&[3u8; 32]).unwrap();
+    OptionContract::new(
+        1622332800,
+        OptAssetParams::new(btc, bene_tkn, 100_000_000),
+        OptAssetParams::new(usd, opt_tkn, 50_000 * 100_000_000),
+        bitcoin::PublicKey::from_str(CTRL_PK).unwrap(),
+        Some(bitcoin::PrivateKey::from_wif(CTRL_SK).unwrap()),
+        btc,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exercise_pset() {
+        let contract = test_contract();
+        let pset = Pset::from_tx(contract.exercise_opt().unwrap());
+        let mut pset = pset_from_base64(&pset_to_base64(&pset)).unwrap();
+        assert_eq!(pset.outputs.len(), 2);
+
+        let addr = contract
+            .deposit_addr(&AddressParams::ELEMENTS)
+            .expect("Valid contract");
+        let prevout = OutPoint::new(elements::Txid::default(), 0);
+        contract
+            .exercise_opt_tx2(&mut pset, prevout, addr)
+            .expect("Valid exercise");
+
+        let (desc, utxo) = contract.spent_cov(SpendType::Exercise).unwrap();
+        let cov_in = pset.inputs.last().unwrap();
+        assert_eq!(cov_in.witness_script, Some(desc.explicit_script()));
+        assert_eq!(cov_in.witness_utxo, Some(utxo));
+        assert_eq!(cov_in.sighash_type, Some(SigHashType::All));
+        assert_eq!(pset.outputs[0].to_txout(), contract.burn_opt(1));
+        assert_eq!(
+            pset.outputs[1].to_txout(),
+            contract.exercise_txout(1).unwrap().0
+        );
+    }
+
+    #[test]
+    fn test_missing_cov_output() {
+        let contract = test_contract();
+        // The cancel outputs are missing from an exercise pset
+        let mut pset = Pset::from_tx(contract.exercise_opt().unwrap());
+        let addr = contract.deposit_addr(&AddressParams::ELEMENTS).unwrap();
+        let prevout = OutPoint::new(elements::Txid::default(), 0);
+        match contract.cancel_tx2(&mut pset, prevout, addr) {
+            Err(Error::MissingCovOutput(_)) => {}
+            r => panic!("Expected missing output, got {:?}", r),
+        }
+        assert!("transfer".parse::<SpendType>().is_err());
+    }
+
+    #[test]
+    fn test_wrong_output_position() {
+        let contract = test_contract();
+        let mut pset = Pset::from_tx(contract.exercise_opt().unwrap());
+        let addr = contract.deposit_addr(&AddressParams::ELEMENTS).unwrap();
+        let prevout = OutPoint::new(elements::Txid::default(), 0);
+        contract
+            .exercise_opt_tx2(&mut pset, prevout, addr)
+            .expect("Valid exercise");
+        pset.outputs.swap(0, 1);
+        match contract.finalize_tx(&mut pset, SpendType::Exercise) {
+            Err(e @ Error::WrongOutputPosition(..)) => assert_eq!(e.exit_code(), 5),
+            r => panic!("Expected wrong output position, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_european_exercise() {
+        use verify::verify_spend;
+
+        let contract = test_contract().with_exercise_window(86400);
+        let start = 1622332800 - 86400;
+        assert_eq!(contract.exercise_start().unwrap(), Some(start));
+        let desc = contract.deposit_desc().unwrap().to_string();
+        assert!(desc.contains(&format!("after({})", start)));
+        assert_ne!(
+            contract.contract_id().unwrap(),
+            test_contract().contract_id().unwrap()
+        );
+
+        // The exercise is only valid from the start of the window
+        let mut pset = Pset::from_tx(contract.exercise_opt().unwrap());
+        assert_eq!(pset.extract_tx().unwrap().lock_time, start);
+        let addr = contract.deposit_addr(&AddressParams::ELEMENTS).unwrap();
+        let prevout = OutPoint::new(elements::Txid::default(), 0);
+        contract
+            .exercise_opt_tx2(&mut pset, prevout, addr)
+            .expect("Valid exercise");
+        assert_eq!(pset.inputs[0].required_time_locktime, Some(start));
+        assert!(pset.extract_tx().unwrap().lock_time >= start);
+
+        // The window does not close at expiry, the covenant cannot bound the lock
+        // time from above and a later exercise is still valid
+        let after_expiry = 1622332800 + 86400;
+        pset.inputs[0].required_time_locktime = Some(after_expiry);
+        contract
+            .finalize_tx(&mut pset, SpendType::Exercise)
+            .expect("Finalized exercise");
+        let tx = pset.extract_tx().unwrap();
+        assert_eq!(tx.lock_time, after_expiry);
+        let report = verify_spend(&contract, &tx, SpendType::Exercise).unwrap();
+        assert!(report.pass, "{:?}", report.failures);
+
+        // The window must not be empty or start before the lock time threshold
+        for &window in &[0, 1622332800] {
+            match test_contract().with_exercise_window(window).deposit_desc() {
+                Err(Error::InvalidExerciseWindow(w)) => assert_eq!(w, window),
+                r => panic!("Expected invalid exercise window, got {:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn test_cash_settlement() {
+        use config::{CTRL_PK, CTRL_SK};
+
+        // A call with a strike of 50k, settled at 60k pays a sixth of the collateral
+        let oracle_sk = bitcoin::PrivateKey::from_wif(CTRL_SK).unwrap();
+        let oracle_pk = bitcoin::PublicKey::from_str(CTRL_PK).unwrap();
+        let prices = vec![40_000 * 100_000_000, 60_000 * 100_000_000];
+        let contract = test_contract().with_cash_settlement(CashSettlement::new(
+            oracle_pk,
+            prices.clone(),
+            false,
+        ));
+        assert_eq!(contract.cash_payout(prices[0]).unwrap(), 0);
+        assert_eq!(contract.cash_payout(prices[1]).unwrap(), 16_666_666);
+        assert_eq!(contract.expiry_claim_locktime(), 1622332800 + 86400);
+        let desc = contract.deposit_desc().unwrap().to_string();
+        assert_eq!(desc.matches("csfs(").count(), 2);
+        assert!(desc.contains(&format!("after({})", 1622332800 + 86400)));
+        match contract.clone().with_exercise_window(86400).deposit_desc() {
+            Err(e @ Error::EuropeanCashSettlement) => assert_eq!(e.exit_code(), 2),
+            r => panic!("Expected european cash settlement, got {:?}", r),
+        }
+
+        // The settlement needs an attested grid price
+        match contract.exercise_opt() {
+            Err(Error::MissingSettlement) => {}
+            r => panic!("Expected missing settlement, got {:?}", r),
+        }
+        let msg = contract.price_message(prices[1]).unwrap();
+        let sig = oracle::sign_price(&oracle_sk, &msg);
+        // Prices off the grid settle at the nearest grid price
+        let off_grid = contract
+            .clone()
+            .with_settlement_price(prices[1] + 1, Some(sig))
+            .unwrap();
+        assert_eq!(off_grid.cash_settlement().unwrap().price, Some(prices[1]));
+        let no_grid =
+            test_contract().with_cash_settlement(CashSettlement::new(oracle_pk, vec![], false));
+        match no_grid.with_settlement_price(prices[1], None) {
+            Err(Error::InvalidSettlementPrice(..)) => {}
+            r => panic!("Expected invalid price, got {:?}", r),
+        }
+        match contract.clone().with_settlement_price(prices[0], Some(sig)) {
+            Err(Error::InvalidOracleSig) => {}
+            r => panic!("Expected invalid oracle signature, got {:?}", r),
+        }
+        let settled = contract
+            .with_settlement_price(prices[1], Some(sig))
+            .unwrap();
+
+        // The bene token holders keep the rest of the collateral
+        let mut pset = Pset::from_tx(settled.exercise_opt().unwrap());
+        let addr = settled.deposit_addr(&AddressParams::ELEMENTS).unwrap();
+        let prevout = OutPoint::new(elements::Txid::default(), 0);
+        settled
+            .exercise_opt_tx2(&mut pset, prevout, addr)
+            .expect("Valid settlement");
+        let locked = settled.locked_asset_params().asset();
+        let payout = pset.outputs.last().unwrap().to_txout();
+        assert_eq!(payout.value, confidential::Value::Explicit(16_666_666));
+        assert_eq!(payout.asset, confidential::Asset::Explicit(locked));
+        assert_eq!(
+            pset.outputs[1].to_txout().value,
+            confidential::Value::Explicit(100_000_000 - 16_666_666)
+        );
+    }
+
+    #[test]
+    fn test_finalize_cash_settlement() {
+        use config::new_private_key;
+        use verify::verify_spend;
+
+        // The oracle is not the control key
+        let secp = secp256k1_zkp::Secp256k1::signing_only();
+        let oracle_sk = new_private_key();
+        let oracle_pk = oracle_sk.public_key(&secp);
+        let prices = vec![40_000 * 100_000_000, 60_000 * 100_000_000];
+        let contract = test_contract().with_cash_settlement(CashSettlement::new(
+            oracle_pk,
+            prices.clone(),
+            false,
+        ));
+        assert_ne!(contract.control_key(), oracle_pk);
+        // The oracle attests the grid price of the observed price off the grid
+        let observed = 57_000 * 100_000_000;
+        let grid_price = oracle::grid_price(&prices, observed).unwrap();
+        assert_eq!(grid_price, prices[1]);
+        let msg = contract.price_message(grid_price).unwrap();
+        let sig = oracle::sign_price(&oracle_sk, &msg);
+        let settled = contract.with_settlement_price(observed, Some(sig)).unwrap();
+
+        let mut pset = Pset::from_tx(settled.exercise_opt().unwrap());
+        let addr = settled.deposit_addr(&AddressParams::ELEMENTS).unwrap();
+        let prevout = OutPoint::new(elements::Txid::default(), 0);
+        settled
+            .exercise_opt_tx2(&mut pset, prevout, addr)
+            .expect("Valid settlement");
+        settled
+            .finalize_tx(&mut pset, SpendType::Exercise)
+            .expect("Finalized settlement");
+        let tx = pset.extract_tx().unwrap();
+        let report = verify_spend(&settled, &tx, SpendType::Exercise).unwrap();
+        assert!(report.pass, "{:?}", report.failures);
+    }
+
+    #[test]
+    fn test_max_units() {
+        // A contract with `units` tokens and 100 satoshis per token
+        let with_units = |units: u64| {
+            let mut contract = test_contract().with_units(units);
+            contract.locked_asset_params.value = 100 * units;
+            contract.claim_asset_params.value = 100 * units;
+            contract
+        };
+        let max_units = test_contract().max_units().unwrap();
+        assert!(max_units > 1);
+        let script_len = |units: u64| {
+            let desc = with_units(units).deposit_desc().unwrap();
+            desc.explicit_script().len()
+        };
+        let max_len = script_len(max_units);
+        assert!(max_len <= MAX_STANDARD_P2WSH_SCRIPT_SIZE);
+        // One more partial exercise branch does not fit
+        assert!(2 * max_len - script_len(max_units - 1) > MAX_STANDARD_P2WSH_SCRIPT_SIZE);
+        match with_units(max_units + 1).deposit_desc() {
+            Err(Error::TooManyUnits(units, max)) => {
+                assert_eq!((units, max), (max_units + 1, max_units))
+            }
+            r => panic!("Expected too many units, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_settlement_before_expiry() {
+        use config::{CTRL_PK, CTRL_SK};
+        use verify::verify_spend;
+
+        let oracle_sk = bitcoin::PrivateKey::from_wif(CTRL_SK).unwrap();
+        let oracle_pk = bitcoin::PublicKey::from_str(CTRL_PK).unwrap();
+        let prices = vec![40_000 * 100_000_000, 60_000 * 100_000_000];
+        let contract = test_contract().with_cash_settlement(CashSettlement::new(
+            oracle_pk,
+            prices.clone(),
+            false,
+        ));
+        let desc = contract.deposit_desc().unwrap().to_string();
+        assert_eq!(desc.matches("n:after(1622332800),a:and_b(csfs(").count(), 2);
+        let msg = contract.price_message(prices[1]).unwrap();
+        let sig = oracle::sign_price(&oracle_sk, &msg);
+        let settled = contract
+            .with_settlement_price(prices[1], Some(sig))
+            .unwrap();
+
+        // The settlement is only valid from expiry, whatever the oracle signs
+        let mut pset = Pset::from_tx(settled.exercise_opt().unwrap());
+        assert_eq!(pset.extract_tx().unwrap().lock_time, 1622332800);
+        let addr = settled.deposit_addr(&AddressParams::ELEMENTS).unwrap();
+        let prevout = OutPoint::new(elements::Txid::default(), 0);
+        settled
+            .exercise_opt_tx2(&mut pset, prevout, addr)
+            .expect("Valid settlement");
+        assert_eq!(pset.inputs[0].required_time_locktime, Some(1622332800));
+        settled
+            .finalize_tx(&mut pset, SpendType::Exercise)
+            .expect("Finalized settlement");
+        let mut tx = pset.extract_tx().unwrap();
+        tx.lock_time = 1622332800 - 1;
+        let report = verify_spend(&settled, &tx, SpendType::Exercise).unwrap();
+        assert!(!report.pass);
+        assert_eq!(
+            report.failures[0],
+            "after(1622332800): the transaction lock time is 1622332799"
+        );
+    }
+
+    #[test]
+    fn test_private_control_key() {
+        use config::{new_private_key, CTRL_SK};
+
+        let control_sk = new_private_key();
+        let secp = secp256k1_zkp::Secp256k1::signing_only();
+        let mut contract = test_contract();
+        contract.control_key = control_sk.public_key(&secp);
+        contract.control_sk = None;
+
+        let mut pset = Pset::from_tx(contract.exercise_opt().unwrap());
+        let addr = contract.deposit_addr(&AddressParams::ELEMENTS).unwrap();
+        let prevout = OutPoint::new(elements::Txid::default(), 0);
+        contract
+            .exercise_opt_tx2(&mut pset, prevout, addr)
+            .expect("Valid exercise");
+        match contract.finalize_tx(&mut pset.clone(), SpendType::Exercise) {
+            Err(Error::MissingControlKey) => {}
+            r => panic!("Expected missing control key, got {:?}", r),
+        }
+        // Only the secret of the control key can sign the covenant
+        let public_sk = bitcoin::PrivateKey::from_wif(CTRL_SK).unwrap();
+        match contract.clone().with_control_sk(public_sk) {
+            Err(Error::InvalidControlKey) => {}
+            r => panic!("Expected invalid control key, got {:?}", r),
+        }
+        let contract = contract.with_control_sk(control_sk).unwrap();
+        contract
+            .finalize_tx(&mut pset, SpendType::Exercise)
+            .expect("Signed covenant");
+    }
+
+    #[test]
+    fn test_height_expiry() {
+        let mut contract = test_contract();
+        assert!(!contract.expiry_is_height());
+        contract.expiry = 800_000;
+        assert!(contract.expiry_is_height());
+
+        let desc = contract.deposit_desc().unwrap().to_string();
+        assert!(desc.contains("after(800000)"));
+
+        let mut pset = Pset::from_tx(contract.claim_expiry());
+        assert_eq!(pset.extract_tx().unwrap().lock_time, 800_000);
+        let addr = contract.deposit_addr(&AddressParams::ELEMENTS).unwrap();
+        let prevout = OutPoint::new(elements::Txid::default(), 0);
+        contract
+            .claim_expiry_tx2(&mut pset, prevout, addr)
+            .expect("Valid expiry claim");
+        let inp = pset.inputs.last().unwrap();
+        assert_eq!(inp.required_height_locktime, Some(800_000));
+        assert_eq!(inp.required_time_locktime, None);
+    }
+
+    #[test]
+    fn test_partial_exercise() {
+        let contract = test_contract().with_units(4);
+        let desc = contract.deposit_desc().unwrap().to_string();
+        let contract = contract.with_exercise_units(1).unwrap();
+
+        let mut pset = Pset::from_tx(contract.exercise_opt().unwrap());
+        let addr = contract.deposit_addr(&AddressParams::ELEMENTS).unwrap();
+        let prevout = OutPoint::new(elements::Txid::default(), 0);
+        contract
+            .exercise_opt_tx2(&mut pset, prevout, addr)
+            .expect("Valid partial exercise");
+
+        assert_eq!(pset.outputs[0].to_txout(), contract.burn_opt(1));
+        let exercise_txout = pset.outputs[1].to_txout();
+        assert_eq!(
+            exercise_txout.value,
+            confidential::Value::Explicit(50_000 * 100_000_000 / 4)
+        );
+        // The remaining collateral is re-locked in the successor covenant
+        let successor = contract.successor(3).unwrap();
+        let successor_txout = pset.outputs[2].to_txout();
+        assert_eq!(
+            successor_txout.script_pubkey,
+            successor.deposit_desc().unwrap().script_pubkey()
+        );
+        assert_eq!(
+            successor_txout.value,
+            confidential::Value::Explicit(75_000_000)
+        );
+        let released = pset.outputs.last().unwrap().to_txout();
+        assert_eq!(released.value, confidential::Value::Explicit(25_000_000));
+        assert!(desc.contains(&serialize_hex(&successor_txout)));
+
+        match test_contract().with_units(3).deposit_desc() {
+            Err(Error::IndivisibleAmount(3)) => {}
+            r => panic!("Expected indivisible amount, got {:?}", r),
+        }
+        // The size of the covenant is checked before building it
+        match test_contract().with_units(1_000_000).deposit_desc() {
+            Err(e @ Error::TooManyUnits(..)) => assert_eq!(e.exit_code(), 6),
+            r => panic!("Expected too many units, got {:?}", r),
+        }
+        let contract = test_contract()
+            .with_units(4)
+            .with_exercise_units(5)
+            .unwrap();
+        match contract.exercise_opt() {
+            Err(Error::InvalidExerciseUnits(5, 4)) => {}
+            r => panic!("Expected invalid exercise units, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_unique_payout_burn() {
+        // Two exercises of a single token, the second from the successor
+        let first = test_contract()
+            .with_units(4)
+            .with_exercise_units(1)
+            .unwrap();
+        let second = first.successor(3).unwrap().with_exercise_units(1).unwrap();
+        assert_ne!(
+            first.exercise_payout_desc(1).unwrap().script_pubkey(),
+            second.exercise_payout_desc(1).unwrap().script_pubkey()
+        );
+
+        // The burn claiming the first payout cannot claim the second one
+        let mut pset = Pset::from_tx(first.claim_bene());
+        let addr = first.deposit_addr(&AddressParams::ELEMENTS).unwrap();
+        let txid = elements::Txid::default();
+        first
+            .claim_bene_tx2(&mut pset, OutPoint::new(txid, 1), addr.clone())
+            .expect("Valid claim");
+        match second.claim_bene_tx2(&mut pset, OutPoint::new(txid, 5), addr) {
+            Err(Error::MissingCovOutput("burn")) => {}
+            r => panic!("Expected missing burn output, got {:?}", r),
+        }
+        match second.finalize_tx(&mut pset, SpendType::ClaimBene) {
+            Err(Error::MissingCovOutput("burn")) => {}
+            r => panic!("Expected missing burn output, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_unissued_tokens() {
+        let unissued = AssetId::from_slice(&[0u8; 32]).unwrap();
+        let mut contract = test_contract();
+        contract.claim_asset_params.opt_tkn = unissued;
+        contract.locked_asset_params.opt_tkn = unissued;
+        match contract.deposit_desc() {
+            Err(e @ Error::UnissuedTokens) => assert_eq!(e.exit_code(), 10),
+            r => panic!("Expected unissued tokens, got {:?}", r),
+        }
+        // The opt and bene tokens must be distinct
+        let mut contract = test_contract().with_units(4);
+        contract.locked_asset_params.opt_tkn = contract.claim_asset_params.opt_tkn;
+        match contract.deposit_desc() {
+            Err(Error::UnissuedTokens) => {}
+            r => panic!("Expected unissued tokens, got {:?}", r),
+        }
+        match contract.exercise_payout_desc(1) {
+            Err(Error::UnissuedTokens) => {}
+            r => panic!("Expected unissued tokens, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_contract_fee() {
+        let contract = test_contract();
+        let mut tx = contract.exercise_opt().unwrap();
+        tx.output
+            .push(txout(Script::new(), 1_000, contract.btc_asset));
+        let mut pset = Pset::from_tx(tx);
+        let addr = contract.deposit_addr(&AddressParams::ELEMENTS).unwrap();
+        let prevout = OutPoint::new(elements::Txid::default(), 0);
+        let fee = contract
+            .add_contract_with_fee(&mut pset, prevout, addr, SpendType::Exercise, 1.0)
+            .expect("Valid exercise");
+
+        // The fee covers at least the covenant witness
+        let cov_weight = contract
+            .cov_input_weight(&pset, SpendType::Exercise)
+            .unwrap();
+        assert!(fee as usize >= cov_weight / 4);
+        let values: Vec<_> = pset.outputs.iter().map(|o| o.to_txout().value).collect();
+        assert!(values.contains(&confidential::Value::Explicit(1_000 + fee)));
+        assert!(values.contains(&confidential::Value::Explicit(100_000_000 - fee)));
+    }
+}
@@ -0,0 +1,225 @@
+//! Export of the contract descriptors for watch-only wallets
+//!
+//! The descriptors of all the outputs a contract can be locked in are
+//! exported with their checksums: the deposit covenant, the covenants
+//! re-locking the collateral after partial exercises and the outputs paying
+//! the strike at exercise. Elementsd does not know covenant descriptors, so
+//! the outputs are watched by their `addr()` descriptors.
+use elements::{Address, AddressParams, Script};
+use serde::Serialize;
+
+use contract::{Error, OptionContract};
+
+/// The characters of descriptors, in the order of the checksum algorithm
+const INPUT_CHARSET: &str = "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+/// The characters of the checksum
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn poly_mod(mut c: u64, val: u64) -> u64 {
+    let c0 = c >> 35;
+    c = ((c & 0x7ffffffff) << 5) ^ val;
+    if c0 & 1 > 0 {
+        c ^= 0xf5dee51989
+    };
+    if c0 & 2 > 0 {
+        c ^= 0xa9fdca3312
+    };
+    if c0 & 4 > 0 {
+        c ^= 0x1bab10e32d
+    };
+    if c0 & 8 > 0 {
+        c ^= 0x3706b1677a
+    };
+    if c0 & 16 > 0 {
+        c ^= 0x644d626ffd
+    };
+    c
+}
+
+/// The 8 character checksum of the descriptor string as computed by
+/// `getdescriptorinfo`, or None if the descriptor has invalid characters
+pub fn desc_checksum(desc: &str) -> Option<String> {
+    let mut c = 1;
+    let mut cls = 0;
+    let mut clscount = 0;
+    for ch in desc.chars() {
+        let pos = INPUT_CHARSET.find(ch)? as u64;
+        c = poly_mod(c, pos & 31);
+        cls = cls * 3 + (pos >> 5);
+        clscount += 1;
+        if clscount == 3 {
+            c = poly_mod(c, cls);
+            cls = 0;
+            clscount = 0;
+        }
+    }
+    if clscount > 0 {
+        c = poly_mod(c, cls);
+    }
+    for _ in 0..8 {
+        c = poly_mod(c, 0);
+    }
+    c ^= 1;
+    let checksum = (0..8)
+        .map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char)
+        .collect();
+    Some(checksum)
+}
+
+/// The descriptor with its checksum, replacing any existing checksum
+pub fn with_checksum(desc: &str) -> String {
+    let desc = desc.split('#').next().unwrap_or("");
+    let checksum = desc_checksum(desc).expect("Descriptors have valid characters");
+    format!("{}#{}", desc, checksum)
+}
+
+/// The output of the contract described by a descriptor
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DescriptorRole {
+    /// The covenant locking the collateral of the contract
+    Deposit,
+    /// The covenant re-locking the collateral of the tokens remaining after a partial exercise
+    Successor,
+    /// The output paying the strike of the exercised tokens, claimed with the bene tokens
+    ExercisePayout,
+}
+
+/// A checksummed descriptor of a contract output
+#[derive(Debug, Clone, Serialize)]
+pub struct ContractDescriptor {
+    pub role: DescriptorRole,
+    /// The number of tokens of the covenant, or the exercised tokens of the payout
+    pub units: u64,
+    /// The number of tokens remaining after the exercise of the payout
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining: Option<u64>,
+    pub descriptor: String,
+    pub address: Address,
+    pub script_pubkey: Script,
+}
+
+impl ContractDescriptor {
+    fn new(
+        role: DescriptorRole,
+        units: u64,
+        desc: String,
+        script_pubkey: Script,
+        params: &'static AddressParams,
+    ) -> Self {
+        let address =
+            Address::from_script(&script_pubkey, None, params).expect("Covenants are p2wsh");
+        ContractDescriptor {
+            role,
+            units,
+            remaining: None,
+            descriptor: with_checksum(&desc),
+            address,
+            script_pubkey,
+        }
+    }
+
+    /// The checksummed `addr()` descriptor of the output for `scantxoutset`
+    pub fn scan_object(&self) -> String {
+        with_checksum(&format!("addr({})", self.address))
+    }
+}
+
+/// The descriptors of all the outputs the contract can lock its collateral or
+/// the strike in, with the addresses on the network of `params`
+pub fn contract_descriptors(
+    contract: &OptionContract,
+    params: &'static AddressParams,
+) -> Result<Vec<ContractDescriptor>, Error> {
+    let desc = contract.deposit_desc()?;
+    let mut descs = vec![ContractDescriptor::new(
+        DescriptorRole::Deposit,
+        contract.units(),
+        desc.to_string(),
+        desc.script_pubkey(),
+        params,
+    )];
+    // Cash-settled options are settled for all the tokens at once
+    let partial_units = match contract.cash_settlement() {
+        Some(_) => contract.units(),
+        None => 1,
+    };
+    for units in (partial_units..contract.units()).rev() {
+        let desc = contract.successor(units)?.deposit_desc()?;
+        descs.push(ContractDescriptor::new(
+            DescriptorRole::Successor,
+            units,
+            desc.to_string(),
+            desc.script_pubkey(),
+            params,
+        ));
+    }
+    // Each exercise pays the strike to the payout of the exercised and the
+    // remaining tokens, from the deposit or from any successor covenant
+    for covenant_units in (partial_units..=contract.units()).rev() {
+        let covenant = contract.successor(covenant_units)?;
+        let min_units = match contract.cash_settlement() {
+            Some(_) => covenant_units,
+            None => 1,
+        };
+        for units in min_units..=covenant_units {
+            let desc = covenant.exercise_payout_desc(units)?;
+            let mut payout = ContractDescriptor::new(
+                DescriptorRole::ExercisePayout,
+                units,
+                desc.to_string(),
+                desc.script_pubkey(),
+                params,
+            );
+            payout.remaining = Some(covenant_units - units);
+            descs.push(payout);
+        }
+    }
+    Ok(descs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use contract::test_contract;
+
+    #[test]
+    fn test_desc_checksum() {
+        assert_eq!(desc_checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+        assert_eq!(
+            with_checksum("raw(deadbeef)#qqqqqqqq"),
+            "raw(deadbeef)#89f8spxm"
+        );
+        assert_eq!(desc_checksum("raw(deadbeef)\u{e9}"), None);
+    }
+
+    #[test]
+    fn test_contract_descriptors() {
+        let contract = test_contract().with_units(2);
+        let descs = contract_descriptors(&contract, &AddressParams::ELEMENTS).unwrap();
+        let roles: Vec<_> = descs
+            .iter()
+            .map(|d| (d.role, d.units, d.remaining))
+            .collect();
+        assert_eq!(
+            roles,
+            vec![
+                (DescriptorRole::Deposit, 2, None),
+                (DescriptorRole::Successor, 1, None),
+                (DescriptorRole::ExercisePayout, 1, Some(1)),
+                (DescriptorRole::ExercisePayout, 2, Some(0)),
+                (DescriptorRole::ExercisePayout, 1, Some(0)),
+            ]
+        );
+        let deposit = contract.deposit_addr(&AddressParams::ELEMENTS).unwrap();
+        assert_eq!(descs[0].address, deposit);
+        assert!(descs[0].descriptor.starts_with("elcovwsh("));
+        assert!(descs
+            .iter()
+            .all(|d| d.descriptor.split('#').nth(1).unwrap().len() == 8));
+        assert_eq!(
+            descs[0].scan_object(),
+            with_checksum(&format!("addr({})", deposit))
+        );
+    }
+}
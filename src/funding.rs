@@ -0,0 +1,491 @@
+//! Funding of contract operations from a list of utxos
+//!
+//! Selects the inputs paying the outputs of the partial transaction and the
+//! fees without elementsd, so that watch-only wallets and wallets other than
+//! Elements Core can fund contract operations. The resulting pset must still
+//! be blinded and signed by the owner of the utxos.
+use bitcoin::hashes::{sha256, Hash};
+use elements::pset::{self, PartiallySignedTransaction as Pset};
+use elements::secp256k1_zkp::{Generator, PedersenCommitment, Secp256k1, Tweak, ZERO_TWEAK};
+use elements::{
+    confidential, Address, AssetId, ContractHash, OutPoint, Script, Transaction, TxIn, TxOut, Txid,
+};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+
+use contract::{
+    addr_output, output_weight, txout, varint_len, Error, OptionContract, SpendType,
+    INPUT_BASE_WEIGHT, INPUT_EMPTY_WITNESS_WEIGHT,
+};
+
+/// Change below this value is added to the fee instead of creating an output
+pub const DUST_VALUE: u64 = 546;
+/// Weight of the witness of a p2wpkh input with a high-s signature
+const P2WPKH_WITNESS_WEIGHT: usize = 1 + (1 + 72) + (1 + 33);
+/// Size of the script sig pushing the p2wpkh redeem script of a p2sh-p2wpkh input
+const P2SH_P2WPKH_SCRIPT_SIG_SIZE: usize = 1 + 22;
+/// Size of the script sig of a p2pkh input
+const P2PKH_SCRIPT_SIG_SIZE: usize = (1 + 72) + (1 + 33);
+/// Weight of an asset issuance with an explicit amount and no reissuance tokens:
+/// the blinding nonce, the entropy, the amount and the null inflation keys
+const ISSUANCE_WEIGHT: usize = (32 + 32 + 9 + 1) * 4;
+
+/// A 32 byte asset or value blinding factor. Encoded in hex as displayed
+/// by `listunspent`, i.e. byte-reversed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BlindingFactor([u8; 32]);
+
+impl BlindingFactor {
+    pub fn from_slice(sl: &[u8]) -> Option<BlindingFactor> {
+        if sl.len() != 32 {
+            return None;
+        }
+        let mut inner = [0u8; 32];
+        inner.copy_from_slice(sl);
+        Some(BlindingFactor(inner))
+    }
+
+    /// Parse the byte-reversed hex encoding
+    pub fn from_hex(s: &str) -> Option<BlindingFactor> {
+        let mut bytes = hex::decode(s).ok()?;
+        bytes.reverse();
+        BlindingFactor::from_slice(&bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// The zero blinding factor of explicit assets and values
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0u8; 32]
+    }
+}
+
+impl fmt::Display for BlindingFactor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for b in self.0.iter().rev() {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for BlindingFactor {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for BlindingFactor {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        BlindingFactor::from_hex(&s)
+            .ok_or_else(|| de::Error::custom("expected a 32 byte hex blinding factor"))
+    }
+}
+
+/// An unspent output owned by the wallet funding the transaction. The field
+/// names of `listunspent` are accepted as aliases.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Utxo {
+    pub txid: Txid,
+    pub vout: u32,
+    pub asset: AssetId,
+    /// The unblinded value in sats
+    pub value: u64,
+    /// The asset blinding factor if the asset is confidential
+    #[serde(
+        default,
+        alias = "assetblinder",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub asset_blinder: Option<BlindingFactor>,
+    /// The value blinding factor if the value is confidential
+    #[serde(
+        default,
+        alias = "amountblinder",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub value_blinder: Option<BlindingFactor>,
+    #[serde(alias = "scriptPubKey")]
+    pub script_pubkey: Script,
+    /// The maximum weight of the script sig and the witness spending the utxo.
+    /// Required for scripts other than p2wpkh, p2sh-p2wpkh and p2pkh
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_satisfaction_weight: Option<usize>,
+}
+
+impl Utxo {
+    pub fn outpoint(&self) -> OutPoint {
+        OutPoint::new(self.txid, self.vout)
+    }
+
+    /// The txout of the utxo with the asset and value commitments computed
+    /// from the blinding factors
+    pub fn txout(&self) -> Result<TxOut, Error> {
+        let secp = Secp256k1::new();
+        let tweak = |bf: BlindingFactor| {
+            Tweak::from_slice(bf.as_bytes())
+                .map_err(|_| Error::InvalidUtxo(self.outpoint(), "invalid blinding factor"))
+        };
+        let mut out = txout(self.script_pubkey.clone(), self.value, self.asset);
+        let generator = match self.asset_blinder.filter(|bf| !bf.is_zero()) {
+            Some(abf) => {
+                let gen = Generator::new_blinded(&secp, self.asset.into_tag(), tweak(abf)?);
+                out.asset = confidential::Asset::Confidential(gen);
+                gen
+            }
+            None => Generator::new_unblinded(&secp, self.asset.into_tag()),
+        };
+        if let Some(vbf) = self.value_blinder.filter(|bf| !bf.is_zero()) {
+            let comm = PedersenCommitment::new(&secp, self.value, tweak(vbf)?, generator);
+            out.value = confidential::Value::Confidential(comm);
+        }
+        Ok(out)
+    }
+
+    /// The maximum weight of the script sig and the witness of the input.
+    /// p2sh outputs are assumed to be p2sh-p2wpkh as used by the Elements wallet
+    pub fn satisfaction_weight(&self) -> Result<usize, Error> {
+        let spk = &self.script_pubkey;
+        if let Some(weight) = self.max_satisfaction_weight {
+            Ok(weight)
+        } else if spk.is_v0_p2wpkh() {
+            Ok(P2WPKH_WITNESS_WEIGHT)
+        } else if spk.is_p2sh() {
+            Ok(P2SH_P2WPKH_SCRIPT_SIG_SIZE * 4 + P2WPKH_WITNESS_WEIGHT)
+        } else if spk.is_p2pkh() {
+            // + 1 for the empty witness
+            Ok(P2PKH_SCRIPT_SIG_SIZE * 4 + 1)
+        } else {
+            Err(Error::InvalidUtxo(
+                self.outpoint(),
+                "unknown script type, set max_satisfaction_weight",
+            ))
+        }
+    }
+}
+
+/// Read the JSON list of utxos at `path`
+pub fn utxos_from_file(path: &str) -> Result<Vec<Utxo>, Error> {
+    let file = File::open(path).map_err(|e| Error::UtxoFile(path.to_owned(), e))?;
+    serde_json::from_reader(file).map_err(Error::MalformedUtxos)
+}
+
+// The utxos of `asset` ordered by value, largest first
+fn utxos_by_value(utxos: &[Utxo], asset: AssetId) -> Vec<&Utxo> {
+    let mut utxos: Vec<&Utxo> = utxos.iter().filter(|u| u.asset == asset).collect();
+    utxos.sort_by(|a, b| b.value.cmp(&a.value));
+    utxos
+}
+
+// The estimated weight of the pset after blinding and signing. The weight of the
+// inputs is the weight of the script sigs and witnesses in `satisfaction_weights`
+fn tx_weight(pset: &Pset, satisfaction_weights: &[usize]) -> usize {
+    // version, flag, locktime and the input and output counts
+    let base = 4 + 1 + 4 + varint_len(pset.inputs.len()) + varint_len(pset.outputs.len());
+    let inputs: usize = satisfaction_weights
+        .iter()
+        .map(|w| INPUT_BASE_WEIGHT + INPUT_EMPTY_WITNESS_WEIGHT + w)
+        .sum();
+    let outputs: usize = (0..pset.outputs.len())
+        .map(|i| output_weight(pset, i))
+        .sum();
+    base * 4 + inputs + outputs
+}
+
+// Add the utxo as an input and its satisfaction weight to `weights`
+fn add_input(pset: &mut Pset, weights: &mut Vec<usize>, utxo: &Utxo) -> Result<(), Error> {
+    let mut inp = pset::Input::from_prevout(utxo.outpoint());
+    inp.witness_utxo = Some(utxo.txout()?);
+    weights.push(utxo.satisfaction_weight()?);
+    pset.add_input(inp);
+    Ok(())
+}
+
+/// The asset and the explicit amount issued by the input, if any
+pub fn issued_amount(txin: &TxIn) -> Option<(AssetId, u64)> {
+    if !txin.has_issuance() {
+        return None;
+    }
+    let issuance = &txin.asset_issuance;
+    let entropy = if issuance.asset_blinding_nonce == ZERO_TWEAK {
+        let contract_hash = ContractHash::from_inner(issuance.asset_entropy);
+        AssetId::generate_asset_entropy(txin.previous_output, contract_hash)
+    } else {
+        // Reissuances commit to the entropy of the original issuance
+        sha256::Midstate::from_inner(issuance.asset_entropy)
+    };
+    match issuance.amount {
+        confidential::Value::Explicit(amount) => Some((AssetId::from_entropy(entropy), amount)),
+        _ => None,
+    }
+}
+
+// The fee at `fee_rate` sat/vB for `weight`
+fn fee_for_weight(weight: usize, fee_rate: f64) -> u64 {
+    (weight as f64 / 4.0 * fee_rate).ceil() as u64
+}
+
+/// Funds the partial transaction from the utxos at `fee_rate` sat/vB, as done by
+/// `fundrawtransaction`. The inputs of the partial transaction must be in `utxos`,
+/// their value and the amounts they issue are used for the outputs first. The change
+/// of every asset is sent to the change address and the fee is paid by an explicit
+/// fee output at the end. The btc change is at least `btc_reserve` so that it can
+/// pay for inputs added later.
+pub fn fund_tx(
+    partial: &Transaction,
+    utxos: &[Utxo],
+    change: &Address,
+    btc_asset: AssetId,
+    fee_rate: f64,
+    btc_reserve: u64,
+) -> Result<Pset, Error> {
+    let pset = Pset::from_tx(partial.clone());
+    fund_pset(pset, utxos, change, btc_asset, fee_rate, btc_reserve)
+}
+
+/// Funds the partial pset like `fund_tx`. The blinding keys of the outputs are
+/// kept and accounted for in the fee.
+pub fn fund_pset(
+    mut pset: Pset,
+    utxos: &[Utxo],
+    change: &Address,
+    btc_asset: AssetId,
+    fee_rate: f64,
+    btc_reserve: u64,
+) -> Result<Pset, Error> {
+    let partial = pset.extract_tx()?;
+    let mut targets = BTreeMap::new();
+    for out in &partial.output {
+        match (out.asset, out.value) {
+            (confidential::Asset::Explicit(asset), confidential::Value::Explicit(value)) => {
+                *targets.entry(asset).or_insert(0) += value
+            }
+            _ => return Err(Error::ExpectedExplicitAsset),
+        }
+    }
+    let btc_target = targets.remove(&btc_asset).unwrap_or(0);
+
+    let mut weights = vec![];
+    let mut available = BTreeMap::new();
+    for (i, txin) in partial.input.iter().enumerate() {
+        let prevout = txin.previous_output;
+        let utxo = utxos
+            .iter()
+            .find(|u| u.outpoint() == prevout)
+            .ok_or(Error::InvalidUtxo(
+                prevout,
+                "input of the partial tx is not in the utxos",
+            ))?;
+        pset.inputs[i].witness_utxo = Some(utxo.txout()?);
+        weights.push(utxo.satisfaction_weight()?);
+        *available.entry(utxo.asset).or_insert(0) += utxo.value;
+        if let Some((asset, amount)) = issued_amount(txin) {
+            *available.entry(asset).or_insert(0) += amount;
+        }
+    }
+    let is_unspent = |utxo: &&Utxo| {
+        !partial
+            .input
+            .iter()
+            .any(|txin| txin.previous_output == utxo.outpoint())
+    };
+
+    for (&asset, &target) in &targets {
+        let mut total = available.remove(&asset).unwrap_or(0);
+        for utxo in utxos_by_value(utxos, asset).into_iter().filter(&is_unspent) {
+            if total >= target {
+                break;
+            }
+            add_input(&mut pset, &mut weights, utxo)?;
+            total += utxo.value;
+        }
+        if total < target {
+            return Err(Error::InsufficientFunds(asset, target - total));
+        }
+        if total > target {
+            pset.add_output(addr_output(change, total - target, asset));
+        }
+    }
+    // Return the other assets of the partial tx inputs
+    let mut total = available.remove(&btc_asset).unwrap_or(0);
+    for (&asset, &value) in available.iter().filter(|&(_, &value)| value > 0) {
+        pset.add_output(addr_output(change, value, asset));
+    }
+
+    // Add btc inputs until they pay for the outputs, the fee and the reserve
+    let issuance_weight =
+        partial.input.iter().filter(|i| i.has_issuance()).count() * ISSUANCE_WEIGHT;
+    let mut candidates = utxos_by_value(utxos, btc_asset)
+        .into_iter()
+        .filter(&is_unspent);
+    loop {
+        let mut funded = pset.clone();
+        funded.add_output(addr_output(change, 0, btc_asset));
+        funded.add_output(pset::Output::from_txout(txout(Script::new(), 0, btc_asset)));
+        let weight = tx_weight(&funded, &weights) + issuance_weight;
+        let fee = fee_for_weight(weight, fee_rate);
+
+        let required = btc_target + fee + btc_reserve;
+        if total >= required {
+            let change_value = total - btc_target - fee;
+            let mut funded = pset;
+            let fee = if change_value < DUST_VALUE && btc_reserve == 0 {
+                total - btc_target
+            } else {
+                funded.add_output(addr_output(change, change_value, btc_asset));
+                fee
+            };
+            funded.add_output(pset::Output::from_txout(txout(
+                Script::new(),
+                fee,
+                btc_asset,
+            )));
+            return Ok(funded);
+        }
+        match candidates.next() {
+            Some(utxo) => {
+                add_input(&mut pset, &mut weights, utxo)?;
+                total += utxo.value;
+            }
+            None => return Err(Error::InsufficientFunds(btc_asset, required - total)),
+        }
+    }
+}
+
+/// Funds the contract operation `ty` from the utxos and adds the contract input
+/// and the output sending the unlocked asset to `addr`. Returns the unsigned
+/// pset and the fee paid for the covenant input and the contract output.
+pub fn fund_contract_spend(
+    contract: &OptionContract,
+    ty: SpendType,
+    prevout: OutPoint,
+    addr: Address,
+    utxos: &[Utxo],
+    change: &Address,
+    fee_rate: f64,
+) -> Result<(Pset, u64), Error> {
+    let partial = contract.partial_tx(ty)?;
+    let mut reserve = 0;
+    loop {
+        let mut pset = fund_tx(
+            &partial,
+            utxos,
+            change,
+            contract.btc_asset(),
+            fee_rate,
+            reserve,
+        )?;
+        match contract.add_contract_with_fee(&mut pset, prevout, addr.clone(), ty, fee_rate) {
+            Ok(fee) => return Ok((pset, fee)),
+            // Keep enough btc change to pay for the covenant input
+            Err(Error::InsufficientFee(fee)) if reserve <= fee => reserve = fee + DUST_VALUE,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::CTRL_PK;
+    use contract::test_contract;
+    use elements::AddressParams;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+    use test_utils::test_utxo;
+
+    fn explicit(txout: &TxOut) -> (AssetId, i64) {
+        match (txout.asset, txout.value) {
+            (confidential::Asset::Explicit(asset), confidential::Value::Explicit(value)) => {
+                (asset, value as i64)
+            }
+            _ => panic!("Expected explicit txout"),
+        }
+    }
+
+    // The value of the inputs minus the outputs of every asset
+    fn balance(pset: &Pset) -> HashMap<AssetId, i64> {
+        let mut balance = HashMap::new();
+        for inp in &pset.inputs {
+            let (asset, value) = explicit(inp.witness_utxo.as_ref().unwrap());
+            *balance.entry(asset).or_insert(0) += value;
+        }
+        for out in &pset.outputs {
+            let (asset, value) = explicit(&out.to_txout());
+            *balance.entry(asset).or_insert(0) -= value;
+        }
+        balance
+    }
+
+    #[test]
+    fn test_fund_contract_spend() {
+        let contract = test_contract();
+        let pk = bitcoin::PublicKey::from_str(CTRL_PK).unwrap();
+        let change = Address::p2wpkh(&pk, None, &AddressParams::ELEMENTS);
+        let btc = contract.btc_asset();
+        let (usd, opt_tkn) = {
+            let claim = contract.claim_asset_params();
+            (claim.asset(), claim.opt_tkn())
+        };
+        let strike = contract.claim_asset_params().value();
+        let mut utxos = vec![
+            test_utxo(0, opt_tkn, 1),
+            test_utxo(1, usd, strike / 2),
+            test_utxo(2, usd, strike),
+            test_utxo(3, btc, 10_000),
+        ];
+        let prevout = OutPoint::new(Txid::default(), 9);
+
+        let (pset, fee) = fund_contract_spend(
+            &contract,
+            SpendType::Exercise,
+            prevout,
+            change.clone(),
+            &utxos,
+            &change,
+            1.0,
+        )
+        .expect("Funded exercise");
+        assert!(fee > 0);
+        // Only the largest usd utxo is spent along with the opt token and btc
+        assert_eq!(pset.inputs.len(), 4);
+        // The fee output is last and the inputs pay exactly for the outputs
+        let fee_out = pset.outputs.last().unwrap().to_txout();
+        assert!(fee_out.script_pubkey.is_empty());
+        assert!(explicit(&fee_out).1 > fee as i64);
+        assert!(balance(&pset).values().all(|&v| v == 0));
+        assert_eq!(balance(&pset).get(&btc), Some(&0));
+
+        // Missing the opt token
+        utxos.remove(0);
+        match fund_contract_spend(
+            &contract,
+            SpendType::Exercise,
+            prevout,
+            change.clone(),
+            &utxos,
+            &change,
+            1.0,
+        ) {
+            Err(Error::InsufficientFunds(asset, 1)) => assert_eq!(asset, opt_tkn),
+            r => panic!("Expected insufficient funds, got {:?}", r.map(|r| r.1)),
+        }
+    }
+
+    #[test]
+    fn test_blinding_factor() {
+        let hex = "0100000000000000000000000000000000000000000000000000000000000002";
+        let bf = BlindingFactor::from_hex(hex).unwrap();
+        assert_eq!(bf.as_bytes()[0], 2);
+        assert_eq!(bf.to_string(), hex);
+        assert!(BlindingFactor::from_hex("00").is_none());
+        assert!(BlindingFactor::from_hex(&"00".repeat(32))
+            .unwrap()
+            .is_zero());
+    }
+}
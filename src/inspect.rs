@@ -0,0 +1,403 @@
+//! Decoding of option covenants back into the option terms
+//!
+//! The covenant created by `OptionContract::deposit_desc` commits to the
+//! terms of the option in the `after()` value of the expiry branch and the
+//! txouts of the `outputs_pref` fragments. Cash-settled covenants commit to the
+//! oracle key and the price messages of the `csfs` fragments instead of the
+//! claim asset and the strike. The decoded terms are checked by rebuilding the
+//! covenant from them, so any other covenant is rejected.
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::PublicKey;
+use elements::encode::deserialize_partial;
+use elements::{confidential, secp256k1_zkp, Address, AddressParams, AssetId, Script, TxOut};
+use hex;
+use miniscript::descriptor::CovenantDescriptor;
+use miniscript::{Descriptor, DescriptorTrait};
+use serde::Serialize;
+use std::str::FromStr;
+
+use config::CTRL_SK;
+use contract::{Error, OptAssetParams, OptionContract, LOCKTIME_THRESHOLD};
+use oracle::{CashSettlement, ORACLE_TIMEOUT_BLOCKS, ORACLE_TIMEOUT_SECS};
+
+/// A settlement branch of a cash-settled covenant
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SettlementBranch {
+    /// The price message the oracle signs to unlock the branch. The price is
+    /// only committed to through the message, see `OptionContract::price_message`
+    pub price_message: sha256::Hash,
+    /// The amount in sats of the locked asset kept by the bene token holders
+    pub bene_amount: u64,
+}
+
+/// The option terms committed to by an option covenant
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CovenantTerms {
+    /// The key verifying the covenant
+    pub control_key: PublicKey,
+    /// The `after()` value of the expiry branch, a block height or a timestamp
+    pub expiry: u32,
+    /// The exercise window opening before the expiry of european options, from
+    /// the `after()` values of the exercise branches
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exercise_window: Option<u32>,
+    /// The number of opt and bene tokens covering the locked asset
+    pub units: u64,
+    pub opt_token: AssetId,
+    pub bene_token: AssetId,
+    /// The asset paid at exercise. None for cash-settled covenants
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub claim_asset: Option<AssetId>,
+    /// The strike amount in sats of the claim asset for all the tokens.
+    /// None for cash-settled covenants
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strike: Option<u64>,
+    /// The locked asset. Only committed to by covenants with more than one token,
+    /// in the collateral re-locked by partial exercises, and by cash-settled
+    /// covenants, in the share of the bene token holders
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locked_asset: Option<AssetId>,
+    /// The amount in sats of the locked asset, known along with `locked_asset`
+    /// for covenants with more than one token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locked_asset_amount: Option<u64>,
+    /// The key of the oracle of cash-settled covenants
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oracle_pk: Option<PublicKey>,
+    /// The settlement branches of cash-settled covenants, one for each grid price
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub settlement_grid: Vec<SettlementBranch>,
+}
+
+impl CovenantTerms {
+    /// The contract with the terms. Covenants do not commit to the locked asset
+    /// of a single token or to the btc asset, they are set to the claim asset.
+    /// Cash-settled covenants do not commit to the claim asset, the strike and
+    /// the grid prices, so the contract can only spend their expiry and cancel
+    /// branches. The control key is public if it is the key of `CTRL_SK`.
+    pub fn contract(&self) -> OptionContract {
+        let secp = secp256k1_zkp::Secp256k1::signing_only();
+        let public_sk = bitcoin::PrivateKey::from_wif(CTRL_SK).expect("Known private key");
+        let control_sk = Some(public_sk).filter(|sk| sk.public_key(&secp) == self.control_key);
+        let unknown = AssetId::from_slice(&[0u8; 32]).expect("32 byte asset id");
+        let claim_asset = self.claim_asset.or(self.locked_asset).unwrap_or(unknown);
+        let locked_asset = self.locked_asset.unwrap_or(claim_asset);
+        let contract = OptionContract::new(
+            self.expiry,
+            OptAssetParams::new(
+                locked_asset,
+                self.bene_token,
+                self.locked_asset_amount.unwrap_or(0),
+            ),
+            OptAssetParams::new(claim_asset, self.opt_token, self.strike.unwrap_or(0)),
+            self.control_key,
+            control_sk,
+            claim_asset,
+        )
+        .with_units(self.units);
+        let contract = match self.exercise_window {
+            Some(window) => contract.with_exercise_window(window),
+            None => contract,
+        };
+        match self.oracle_pk {
+            Some(oracle_pk) => {
+                contract.with_cash_settlement(CashSettlement::new(oracle_pk, vec![], false))
+            }
+            None => contract,
+        }
+    }
+
+    /// The covenant descriptor with the terms. The settlement branches of
+    /// cash-settled covenants are rebuilt from the decoded settlement grid
+    pub fn deposit_desc(&self) -> Result<Descriptor<PublicKey>, Error> {
+        let contract = self.contract();
+        if self.oracle_pk.is_none() {
+            return contract.deposit_desc();
+        }
+        let grid = self
+            .settlement_grid
+            .iter()
+            .map(|branch| (branch.price_message, branch.bene_amount))
+            .collect::<Vec<_>>();
+        contract.settlement_desc(&grid)
+    }
+
+    /// The deposit address of the covenant
+    pub fn deposit_addr(&self, addr_params: &'static AddressParams) -> Result<Address, Error> {
+        Ok(self.deposit_desc()?.address(addr_params)?)
+    }
+
+    /// The ID of the contract, as in the registry. This is the sha256 of the
+    /// deposit script pubkey, as in `OptionContract::contract_id`
+    pub fn contract_id(&self) -> Result<sha256::Hash, Error> {
+        let spk = self.deposit_desc()?.script_pubkey();
+        Ok(sha256::Hash::hash(spk.as_bytes()))
+    }
+}
+
+// The arguments of all the fragments `name(..)` in the descriptor string
+fn fragment_args<'a>(desc: &'a str, name: &str) -> Vec<&'a str> {
+    let pattern = format!("{}(", name);
+    desc.match_indices(&pattern[..])
+        .filter(|&(i, _)| !desc[..i].ends_with(|c: char| c.is_alphanumeric() || c == '_'))
+        .filter_map(|(i, _)| {
+            let start = i + pattern.len();
+            desc[start..].find(')').map(|len| &desc[start..start + len])
+        })
+        .collect()
+}
+
+// The txouts serialized back to back in the `outputs_pref` argument
+fn pref_txouts(hex_str: &str) -> Result<Vec<TxOut>, Error> {
+    let bytes = hex::decode(hex_str).map_err(|_| Error::MalformedCovenant("invalid hex"))?;
+    let mut txouts = vec![];
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let (txout, len) = deserialize_partial::<TxOut>(&bytes[pos..])
+            .map_err(|_| Error::MalformedCovenant("invalid outputs_pref txout"))?;
+        txouts.push(txout);
+        pos += len;
+    }
+    Ok(txouts)
+}
+
+fn explicit(txout: &TxOut) -> Result<(AssetId, u64), Error> {
+    match (txout.asset, txout.value) {
+        (confidential::Asset::Explicit(asset), confidential::Value::Explicit(value)) => {
+            Ok((asset, value))
+        }
+        _ => Err(Error::MalformedCovenant("confidential outputs_pref txout")),
+    }
+}
+
+// The oracle key and the price message of a `csfs` fragment
+fn csfs_args(args: &str) -> Result<(PublicKey, sha256::Hash), Error> {
+    let mut args = args.split(',');
+    match (args.next(), args.next()) {
+        (Some(pk), Some(msg)) => Ok((
+            PublicKey::from_str(pk).map_err(|_| Error::MalformedCovenant("invalid oracle key"))?,
+            sha256::Hash::from_str(msg)
+                .map_err(|_| Error::MalformedCovenant("invalid price message"))?,
+        )),
+        _ => Err(Error::MalformedCovenant("invalid csfs fragment")),
+    }
+}
+
+// The expiry of a cash-settled option from the `after()` value of its expiry
+// branch, which leaves the oracle time to attest the price
+fn settlement_expiry(after: u32) -> u32 {
+    // Height expiries are below the threshold, before the timeout in blocks
+    if after < LOCKTIME_THRESHOLD + ORACLE_TIMEOUT_BLOCKS {
+        after.wrapping_sub(ORACLE_TIMEOUT_BLOCKS)
+    } else {
+        after.wrapping_sub(ORACLE_TIMEOUT_SECS)
+    }
+}
+
+/// Decodes the terms of the option covenant. Fails if the covenant is not
+/// the covenant of an option with the decoded terms.
+pub fn decode_covenant(desc: &CovenantDescriptor<PublicKey>) -> Result<CovenantTerms, Error> {
+    let desc_str = desc.to_string();
+    let control_key = desc_str
+        .trim_start_matches("elcovwsh(")
+        .split(',')
+        .next()
+        .and_then(|pk| PublicKey::from_str(pk).ok())
+        .ok_or(Error::MalformedCovenant("missing control key"))?;
+    // The expiry branch is first, european exercise branches have the start of the window
+    let afters = fragment_args(&desc_str, "after")
+        .into_iter()
+        .map(|after| after.parse::<u32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| Error::MalformedCovenant("invalid after() value"))?;
+    let expiry = *afters
+        .first()
+        .ok_or(Error::MalformedCovenant("missing expiry branch"))?;
+    // The branches are expiry, cancel, exercise and the partial exercises,
+    // or expiry, cancel and the settlement branches of cash-settled covenants
+    let branches = fragment_args(&desc_str, "outputs_pref")
+        .into_iter()
+        .map(pref_txouts)
+        .collect::<Result<Vec<_>, _>>()?;
+    if branches.len() < 3 || branches[0].len() != 1 || branches[2].len() != 2 {
+        return Err(Error::MalformedCovenant(
+            "missing expiry or exercise branch",
+        ));
+    }
+    let (bene_token, units) = explicit(&branches[0][0])?;
+    let (opt_token, _) = explicit(&branches[2][0])?;
+    if units == 0 {
+        return Err(Error::MalformedCovenant("branches do not match the tokens"));
+    }
+    let settlements = fragment_args(&desc_str, "csfs")
+        .into_iter()
+        .map(csfs_args)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut terms = CovenantTerms {
+        control_key,
+        expiry,
+        exercise_window: None,
+        units,
+        opt_token,
+        bene_token,
+        claim_asset: None,
+        strike: None,
+        locked_asset: None,
+        locked_asset_amount: None,
+        oracle_pk: None,
+        settlement_grid: vec![],
+    };
+    if settlements.is_empty() {
+        let (claim_asset, strike) = explicit(&branches[2][1])?;
+        if branches.len() as u64 != units + 2 {
+            return Err(Error::MalformedCovenant("branches do not match the tokens"));
+        }
+        terms.exercise_window = afters.get(1).map(|&start| expiry.wrapping_sub(start));
+        terms.claim_asset = Some(claim_asset);
+        terms.strike = Some(strike);
+        // Exercising a single token re-locks the collateral of the other tokens
+        if let Some(successor) = branches.get(3).and_then(|b| b.get(2)) {
+            let (asset, value) = explicit(successor)?;
+            terms.locked_asset = Some(asset);
+            terms.locked_asset_amount = Some(value / (units - 1) * units);
+        }
+    } else {
+        if branches.len() != settlements.len() + 2 {
+            return Err(Error::MalformedCovenant(
+                "branches do not match the settlement grid",
+            ));
+        }
+        terms.expiry = settlement_expiry(expiry);
+        terms.oracle_pk = Some(settlements[0].0);
+        // The second output of each settlement branch keeps the share of the bene token holders
+        for (&(_, price_message), outputs) in settlements.iter().zip(&branches[2..]) {
+            let (asset, bene_amount) = explicit(
+                outputs
+                    .get(1)
+                    .ok_or(Error::MalformedCovenant("missing settlement output"))?,
+            )?;
+            terms.locked_asset = Some(asset);
+            terms.settlement_grid.push(SettlementBranch {
+                price_message,
+                bene_amount,
+            });
+        }
+    }
+
+    let spk = terms.deposit_desc()?.script_pubkey();
+    if spk != desc.script_pubkey() {
+        return Err(Error::MalformedCovenant(
+            "covenant differs from the option of the decoded terms",
+        ));
+    }
+    Ok(terms)
+}
+
+/// Decodes the terms of the option covenant with the elcovwsh descriptor `desc`
+pub fn decode_descriptor(desc: &str) -> Result<CovenantTerms, Error> {
+    let desc = Descriptor::<PublicKey>::from_str(desc)?;
+    decode_covenant(desc.as_cov()?)
+}
+
+/// Decodes the terms of the option covenant with the witness script `script`
+pub fn decode_script(script: &Script) -> Result<CovenantTerms, Error> {
+    let desc = CovenantDescriptor::<PublicKey>::parse_insane(script)?;
+    decode_covenant(&desc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{new_private_key, CTRL_PK};
+    use contract::test_contract;
+
+    #[test]
+    fn test_decode_covenant() {
+        let styles = vec![
+            test_contract(),
+            test_contract().with_units(4),
+            test_contract().with_exercise_window(86400),
+        ];
+        for contract in styles {
+            let units = contract.units();
+            let desc = contract.deposit_desc().unwrap();
+            let cov = desc.as_cov().unwrap();
+            let terms = decode_covenant(cov).expect("Option covenant");
+            assert_eq!(terms.expiry, contract.expiry());
+            assert_eq!(terms.exercise_window, contract.exercise_window());
+            assert_eq!(terms.units, units);
+            assert_eq!(terms.opt_token, contract.claim_asset_params().opt_tkn());
+            assert_eq!(terms.bene_token, contract.locked_asset_params().opt_tkn());
+            assert_eq!(
+                terms.claim_asset,
+                Some(contract.claim_asset_params().asset())
+            );
+            assert_eq!(terms.strike, Some(contract.claim_asset_params().value()));
+            assert_eq!(terms.oracle_pk, None);
+            assert_eq!(
+                terms.contract_id().unwrap(),
+                contract.contract_id().unwrap()
+            );
+            if units > 1 {
+                assert_eq!(
+                    terms.locked_asset,
+                    Some(contract.locked_asset_params().asset())
+                );
+                assert_eq!(
+                    terms.locked_asset_amount,
+                    Some(contract.locked_asset_params().value())
+                );
+            } else {
+                assert_eq!(terms.locked_asset, None);
+            }
+            let decoded = decode_script(&cov.explicit_script()).expect("Option witness script");
+            assert_eq!(decoded, terms);
+            assert_eq!(decode_descriptor(&desc.to_string()).unwrap(), terms);
+        }
+
+        // Covenants of other contracts are rejected
+        match decode_descriptor(&format!("elcovwsh({},pk({}))", CTRL_PK, CTRL_PK)) {
+            Err(Error::MalformedCovenant(..)) => {}
+            r => panic!("Expected malformed covenant, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_decode_cash_settlement() {
+        let secp = secp256k1_zkp::Secp256k1::signing_only();
+        let oracle_pk = new_private_key().public_key(&secp);
+        let prices = vec![40_000 * 100_000_000, 60_000 * 100_000_000];
+        let contract = test_contract().with_cash_settlement(CashSettlement::new(
+            oracle_pk,
+            prices.clone(),
+            false,
+        ));
+        let desc = contract.deposit_desc().unwrap();
+        let terms = decode_descriptor(&desc.to_string()).expect("Cash-settled covenant");
+        assert_eq!(terms.expiry, contract.expiry());
+        assert_eq!(terms.units, contract.units());
+        assert_eq!(terms.oracle_pk, Some(oracle_pk));
+        assert_eq!((terms.claim_asset, terms.strike), (None, None));
+        assert_eq!(
+            terms.locked_asset,
+            Some(contract.locked_asset_params().asset())
+        );
+        // The grid prices are checked against the price messages
+        let grid = prices
+            .iter()
+            .map(|&price| SettlementBranch {
+                price_message: contract.price_message(price).unwrap(),
+                bene_amount: 100_000_000 - contract.cash_payout(price).unwrap(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(terms.settlement_grid, grid);
+        assert_eq!(
+            terms.contract_id().unwrap(),
+            contract.contract_id().unwrap()
+        );
+        let cov = desc.as_cov().unwrap();
+        let decoded = decode_script(&cov.explicit_script()).expect("Cash-settled witness script");
+        assert_eq!(decoded, terms);
+    }
+}
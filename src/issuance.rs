@@ -0,0 +1,435 @@
+//! Issuance of the opt and bene tokens of an option
+//!
+//! Both tokens are issued in one transaction with the supply of the config and
+//! without reissuance tokens. The contract hash of each issuance is the sha256
+//! of the option terms, binding the asset IDs to the terms of the option.
+//! Buyers of the tokens can verify the issuance transaction against a config.
+use bitcoin::hashes::{sha256, Hash};
+use elements::pset::PartiallySignedTransaction as Pset;
+use elements::secp256k1_zkp::ZERO_TWEAK;
+use elements::{confidential, Address, AssetId, AssetIssuance, ContractHash, OutPoint};
+use elements::{Transaction, TxIn};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+
+use config::{Config, OptionStyle};
+use contract::{addr_output, Error};
+use funding::{self, Utxo};
+use registry::OptionKind;
+
+/// The side of the option represented by a token
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenRole {
+    /// The right to exercise the option
+    Opt,
+    /// The claim on the locked asset or the strike paid at exercise
+    Bene,
+}
+
+/// The option terms committed to by the contract hash of a token issuance
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct TokenTerms {
+    pub kind: OptionKind,
+    pub role: TokenRole,
+    pub expiry: u32,
+    /// The strike amount in sats of the claim asset
+    pub strike: u64,
+    pub lock_asset: AssetId,
+    pub claim_asset: AssetId,
+    pub locked_asset_amount: u64,
+    /// The issued amount of the token
+    pub supply: u64,
+    pub option_style: OptionStyle,
+    /// The exercise window of european options, None for american options
+    pub exercise_window: Option<u32>,
+    /// The key verifying the covenant
+    pub control_pk: bitcoin::PublicKey,
+    /// The oracle attesting the settlement price of cash-settled options
+    pub oracle_pk: Option<bitcoin::PublicKey>,
+    /// The prices cash-settled options can be settled at
+    pub settlement_prices: Vec<u64>,
+}
+
+impl TokenTerms {
+    /// The terms of the token for the option with the assets of the config
+    pub fn new(kind: OptionKind, role: TokenRole, expiry: u32, strike: u64, cfg: &Config) -> Self {
+        TokenTerms {
+            kind,
+            role,
+            expiry,
+            strike,
+            lock_asset: cfg.lock_asset,
+            claim_asset: cfg.claim_asset,
+            locked_asset_amount: cfg.locked_asset_amount,
+            supply: cfg.opt_token_supply,
+            option_style: cfg.option_style,
+            exercise_window: match cfg.option_style {
+                OptionStyle::American => None,
+                OptionStyle::European => Some(cfg.exercise_window),
+            },
+            control_pk: cfg.control_pk,
+            oracle_pk: cfg.oracle_pk,
+            settlement_prices: match cfg.oracle_pk {
+                Some(_) => cfg.settlement_prices.clone(),
+                None => vec![],
+            },
+        }
+    }
+
+    /// The contract hash of the issuance, the sha256 of the JSON serialization of the terms
+    pub fn contract_hash(&self) -> ContractHash {
+        let json = serde_json::to_vec(self).expect("Terms serialize to JSON");
+        ContractHash::hash(&json)
+    }
+
+    /// The asset ID of the token issued by the input spending `prevout`
+    pub fn asset_id(&self, prevout: OutPoint) -> AssetId {
+        AssetId::from_entropy(AssetId::generate_asset_entropy(
+            prevout,
+            self.contract_hash(),
+        ))
+    }
+
+    // The input spending `prevout` issuing the supply of the token without reissuance tokens
+    pub(crate) fn issuance_input(&self, prevout: OutPoint) -> TxIn {
+        let mut txin = TxIn::default();
+        txin.previous_output = prevout;
+        txin.has_issuance = true;
+        txin.asset_issuance = AssetIssuance {
+            amount: confidential::Value::Explicit(self.supply),
+            inflation_keys: confidential::Value::Null,
+            asset_entropy: self.contract_hash().into_inner(),
+            ..Default::default()
+        };
+        txin
+    }
+}
+
+/// The unsigned transaction issuing the opt and bene tokens
+#[derive(Debug, Clone)]
+pub struct TokenIssuance {
+    pub pset: Pset,
+    pub opt_token: AssetId,
+    pub bene_token: AssetId,
+    pub opt_terms: TokenTerms,
+    pub bene_terms: TokenTerms,
+}
+
+/// The `count` utxos issuing tokens, the largest btc utxos first
+pub(crate) fn issuer_utxos<'a>(
+    utxos: &'a [Utxo],
+    cfg: &Config,
+    count: usize,
+) -> Result<Vec<&'a Utxo>, Error> {
+    let mut issuers: Vec<&Utxo> = utxos.iter().collect();
+    issuers.sort_by_key(|u| Reverse((u.asset == cfg.btc_asset, u.value)));
+    if issuers.len() < count {
+        return Err(Error::TooFewUtxos(count));
+    }
+    issuers.truncate(count);
+    Ok(issuers)
+}
+
+/// Issues the opt and bene tokens of the option to `addr`, funded from the utxos
+/// at `fee_rate` sat/vB. The tokens are issued by the two largest btc utxos, or
+/// any other utxos if there are not enough btc utxos.
+pub fn issue_tokens(
+    kind: OptionKind,
+    expiry: u32,
+    strike: u64,
+    cfg: &Config,
+    utxos: &[Utxo],
+    addr: &Address,
+    change: &Address,
+    fee_rate: f64,
+) -> Result<TokenIssuance, Error> {
+    let issuers = issuer_utxos(utxos, cfg, 2)?;
+    let opt_terms = TokenTerms::new(kind, TokenRole::Opt, expiry, strike, cfg);
+    let bene_terms = TokenTerms::new(kind, TokenRole::Bene, expiry, strike, cfg);
+    let (opt_prevout, bene_prevout) = (issuers[0].outpoint(), issuers[1].outpoint());
+    let opt_token = opt_terms.asset_id(opt_prevout);
+    let bene_token = bene_terms.asset_id(bene_prevout);
+
+    let mut partial = Pset::from_tx(Transaction {
+        version: 2,
+        lock_time: 0,
+        input: vec![
+            opt_terms.issuance_input(opt_prevout),
+            bene_terms.issuance_input(bene_prevout),
+        ],
+        output: vec![],
+    });
+    partial.add_output(addr_output(addr, opt_terms.supply, opt_token));
+    partial.add_output(addr_output(addr, bene_terms.supply, bene_token));
+    let pset = funding::fund_pset(partial, utxos, change, cfg.btc_asset, fee_rate, 0)?;
+
+    Ok(TokenIssuance {
+        pset,
+        opt_token,
+        bene_token,
+        opt_terms,
+        bene_terms,
+    })
+}
+
+/// The result of verifying the issuance of a token of the config
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TokenReport {
+    pub role: TokenRole,
+    pub asset: AssetId,
+    /// The outpoint spent by the input issuing the token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issuance_prevout: Option<OutPoint>,
+    /// The explicit issued amount
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contract_hash: Option<ContractHash>,
+    /// The checks the issuance failed, empty if it passed
+    pub failures: Vec<&'static str>,
+    pub pass: bool,
+}
+
+// The entropy of the asset newly issued by the input, if any. Reissuances are ignored.
+fn new_issuance_entropy(txin: &TxIn) -> Option<sha256::Midstate> {
+    let issuance = &txin.asset_issuance;
+    if !txin.has_issuance() || issuance.asset_blinding_nonce != ZERO_TWEAK {
+        return None;
+    }
+    let contract_hash = ContractHash::from_inner(issuance.asset_entropy);
+    Some(AssetId::generate_asset_entropy(
+        txin.previous_output,
+        contract_hash,
+    ))
+}
+
+// Verify that the token `asset` is issued by `tx` with exactly `supply` and no
+// reissuance tokens, and with the contract hash of the terms if known.
+fn verify_token(
+    tx: &Transaction,
+    role: TokenRole,
+    asset: AssetId,
+    supply: u64,
+    terms: Option<&TokenTerms>,
+) -> TokenReport {
+    let mut report = TokenReport {
+        role,
+        asset,
+        issuance_prevout: None,
+        amount: None,
+        contract_hash: None,
+        failures: vec![],
+        pass: false,
+    };
+    let txin = tx
+        .input
+        .iter()
+        .find(|txin| new_issuance_entropy(txin).map(AssetId::from_entropy) == Some(asset));
+    match txin {
+        Some(txin) => {
+            let issuance = &txin.asset_issuance;
+            report.issuance_prevout = Some(txin.previous_output);
+            report.contract_hash = Some(ContractHash::from_inner(issuance.asset_entropy));
+            match issuance.amount {
+                confidential::Value::Explicit(amount) => {
+                    report.amount = Some(amount);
+                    if amount != supply {
+                        report
+                            .failures
+                            .push("issued amount differs from the token supply");
+                    }
+                }
+                _ => report.failures.push("issued amount is not explicit"),
+            }
+            match issuance.inflation_keys {
+                confidential::Value::Null | confidential::Value::Explicit(0) => {}
+                _ => report.failures.push("reissuance tokens are issued"),
+            }
+            if let Some(terms) = terms {
+                if report.contract_hash != Some(terms.contract_hash()) {
+                    report
+                        .failures
+                        .push("contract hash does not commit to the option terms");
+                }
+            }
+        }
+        None => report
+            .failures
+            .push("token is not issued by the transaction"),
+    }
+    report.pass = report.failures.is_empty();
+    report
+}
+
+/// Verifies that the opt and bene tokens of the config are issued by `tx` with
+/// the supply of the config and without reissuance tokens. If the kind, expiry
+/// and strike of the option are given, the contract hashes of the issuances
+/// must also commit to the option terms as done by `issue_tokens`.
+pub fn verify_tokens(
+    tx: &Transaction,
+    cfg: &Config,
+    option: Option<(OptionKind, u32, u64)>,
+) -> Vec<TokenReport> {
+    let terms = |role| {
+        option.map(|(kind, expiry, strike)| TokenTerms::new(kind, role, expiry, strike, cfg))
+    };
+    vec![
+        verify_token(
+            tx,
+            TokenRole::Opt,
+            cfg.opt_token,
+            cfg.opt_token_supply,
+            terms(TokenRole::Opt).as_ref(),
+        ),
+        verify_token(
+            tx,
+            TokenRole::Bene,
+            cfg.bene_token,
+            cfg.opt_token_supply,
+            terms(TokenRole::Bene).as_ref(),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{new_private_key, BTC_ASSET, CTRL_PK};
+    use elements::AddressParams;
+    use funding::issued_amount;
+    use std::str::FromStr;
+    use test_utils::{test_config, test_utxo};
+
+    #[test]
+    fn test_issue_tokens() {
+        let cfg = test_config();
+        let pk = bitcoin::PublicKey::from_str(CTRL_PK).unwrap();
+        let addr = Address::p2wpkh(&pk, None, &AddressParams::ELEMENTS);
+        let btc = AssetId::from_slice(&BTC_ASSET).unwrap();
+        let utxos = vec![
+            test_utxo(0, btc, 1_000),
+            test_utxo(1, btc, 50_000),
+            test_utxo(2, btc, 20_000),
+        ];
+
+        let issuance = issue_tokens(
+            OptionKind::Call,
+            1622332800,
+            50_000 * 100_000_000,
+            &cfg,
+            &utxos[..],
+            &addr,
+            &addr,
+            1.0,
+        )
+        .expect("Funded issuance");
+        assert_ne!(issuance.opt_token, issuance.bene_token);
+        assert_ne!(
+            issuance.opt_terms.contract_hash(),
+            issuance.bene_terms.contract_hash()
+        );
+
+        // The two largest utxos issue exactly one token each without reissuance tokens
+        let tx = issuance.pset.extract_tx().unwrap();
+        assert_eq!(tx.input[0].previous_output, utxos[1].outpoint());
+        assert_eq!(tx.input[1].previous_output, utxos[2].outpoint());
+        assert_eq!(issued_amount(&tx.input[0]), Some((issuance.opt_token, 1)));
+        assert_eq!(issued_amount(&tx.input[1]), Some((issuance.bene_token, 1)));
+        assert!(tx.input[..2]
+            .iter()
+            .all(|txin| txin.asset_issuance.inflation_keys == confidential::Value::Null));
+        let token_out = |asset| {
+            tx.output
+                .iter()
+                .filter(|out| out.asset == confidential::Asset::Explicit(asset))
+                .count()
+        };
+        assert_eq!(token_out(issuance.opt_token), 1);
+        assert_eq!(token_out(issuance.bene_token), 1);
+
+        let mut cfg = cfg;
+        cfg.opt_token = issuance.opt_token;
+        cfg.bene_token = issuance.bene_token;
+        let option = Some((OptionKind::Call, 1622332800, 50_000 * 100_000_000));
+        let passed = |tx: &Transaction, option| {
+            verify_tokens(tx, &cfg, option)
+                .iter()
+                .map(|r| r.pass)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(passed(&tx, option), vec![true, true]);
+        assert_eq!(passed(&tx, None), vec![true, true]);
+        // Terms of a different option
+        assert_eq!(
+            passed(
+                &tx,
+                Some((OptionKind::Put, 1622332800, 50_000 * 100_000_000))
+            ),
+            vec![false, false]
+        );
+        // Wrong supply
+        let mut bad_tx = tx.clone();
+        bad_tx.input[0].asset_issuance.amount = confidential::Value::Explicit(2);
+        assert_eq!(passed(&bad_tx, option), vec![false, true]);
+        // Reissuance tokens
+        let mut bad_tx = tx.clone();
+        bad_tx.input[1].asset_issuance.inflation_keys = confidential::Value::Explicit(1);
+        assert_eq!(passed(&bad_tx, option), vec![true, false]);
+        // Not issued by the transaction
+        let mut bad_tx = tx.clone();
+        bad_tx.input.swap(0, 1);
+        bad_tx.input[0].previous_output.vout = 7;
+        let reports = verify_tokens(&bad_tx, &cfg, option);
+        assert_eq!(
+            reports[1].failures,
+            vec!["token is not issued by the transaction"]
+        );
+
+        match issue_tokens(
+            OptionKind::Call,
+            1622332800,
+            50_000 * 100_000_000,
+            &cfg,
+            &utxos[..1],
+            &addr,
+            &addr,
+            1.0,
+        ) {
+            Err(Error::TooFewUtxos(2)) => {}
+            r => panic!("Expected too few utxos, got {:?}", r.map(|i| i.opt_token)),
+        }
+    }
+
+    #[test]
+    fn test_contract_hash_terms() {
+        let hash = |cfg: &Config| {
+            TokenTerms::new(OptionKind::Call, TokenRole::Opt, 1622332800, 50_000, cfg)
+                .contract_hash()
+        };
+        let cfg = test_config();
+        // The window of american options is not part of the terms
+        let mut american = cfg.clone();
+        american.exercise_window = 86400;
+        assert_eq!(hash(&american), hash(&cfg));
+
+        let mut european = american.clone();
+        european.option_style = OptionStyle::European;
+        let mut counterparty = cfg.clone();
+        let secp = bitcoin::secp256k1::Secp256k1::signing_only();
+        counterparty.control_pk = new_private_key().public_key(&secp);
+        counterparty.control_sk = None;
+        let mut cash = cfg.clone();
+        cash.oracle_pk = Some(cfg.control_pk);
+        cash.settlement_prices = vec![40_000, 60_000];
+        let mut grid = cash.clone();
+        grid.settlement_prices = vec![40_000];
+        let hashes = vec![&cfg, &european, &counterparty, &cash, &grid]
+            .into_iter()
+            .map(hash)
+            .collect::<Vec<_>>();
+        for (i, h) in hashes.iter().enumerate() {
+            assert!(!hashes[..i].contains(h), "Terms {} hash like others", i);
+        }
+    }
+}
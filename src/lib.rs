@@ -0,0 +1,36 @@
+//! Call/Put options on Elements using elements-miniscript covenants
+//!
+//! The library does not have access to a wallet or the blockchain. It creates
+//! the covenant contracts and the psets spending them, which must be funded,
+//! blinded and signed by an Elements wallet.
+extern crate base64;
+extern crate bitcoin;
+extern crate elements;
+extern crate elements_miniscript as miniscript;
+extern crate hex;
+extern crate serde;
+extern crate serde_json;
+extern crate serde_yaml;
+
+pub mod blinding;
+pub mod chain;
+pub mod config;
+pub mod contract;
+pub mod export;
+pub mod funding;
+pub mod inspect;
+pub mod issuance;
+pub mod network;
+pub mod offer;
+pub mod oracle;
+pub mod registry;
+pub mod rpc;
+#[cfg(test)]
+mod test_utils;
+pub mod verify;
+
+pub use config::Config;
+pub use contract::{Error, OptAssetParams, OptionContract, SpendType};
+pub use funding::Utxo;
+pub use network::Network;
+pub use registry::{ContractEntry, ContractState, ExercisePayout, OptionKind, Registry};
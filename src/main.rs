@@ -1,6 +1,6 @@
 extern crate bitcoin;
 extern crate elements;
-extern crate elements_miniscript as miniscript;
+extern crate options_elements;
 
 /// Code from hal project by Steven Roose
 extern crate log;
@@ -85,17 +85,30 @@ fn main() {
         false => setup_logger(log::LevelFilter::Warn),
     }
 
-    match matches.subcommand() {
+    let res = match matches.subcommand() {
         ("", _) => {
             app.write_help(&mut io::stderr()).unwrap();
             io::stderr().write(b"\n").unwrap();
             process::exit(1);
         }
         ("call", Some(ref m)) => cmd::call::execute(&m),
+        ("put", Some(ref m)) => cmd::put::execute(&m),
+        ("contract", Some(ref m)) => cmd::contract::execute(&m),
         ("init", Some(ref m)) => cmd::config::exec_init(&m),
+        ("issue", Some(ref m)) => cmd::tokens::exec_issue(&m),
+        ("verify-tokens", Some(ref m)) => cmd::tokens::exec_verify_tokens(&m),
+        ("inspect", Some(ref m)) => cmd::inspect::exec_inspect(&m),
+        ("oracle", Some(ref m)) => cmd::oracle::execute(&m),
+        ("offer", Some(ref m)) => cmd::offer::execute(&m),
         (cmd, _subcommand_args) => {
             // Try execute an external subcommand.
             panic!("no such subcommand: `{}`", cmd);
         }
+    };
+
+    // Exit with a distinct code per failure class
+    if let Err(e) = res {
+        eprintln!("Execution failed: {}", e);
+        process::exit(e.exit_code());
     }
 }
@@ -0,0 +1,124 @@
+//! Elements networks the contracts can be created on
+use elements::AddressParams;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Mutex;
+
+/// The Liquid testnet parameters
+pub const LIQUID_TESTNET: AddressParams = AddressParams {
+    p2pkh_prefix: 36,
+    p2sh_prefix: 19,
+    blinded_prefix: 23,
+    bech_hrp: "tex",
+    blech_hrp: "tlq",
+};
+
+/// User defined address parameters for a custom Elements chain
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct CustomParams {
+    pub p2pkh_prefix: u8,
+    pub p2sh_prefix: u8,
+    pub blinded_prefix: u8,
+    /// The human readable part of segwit addresses
+    pub bech_hrp: String,
+    /// The human readable part of confidential segwit addresses
+    pub blech_hrp: String,
+}
+
+// The leaked address parameters of the custom networks used by the process
+static CUSTOM_PARAMS: Mutex<Vec<(CustomParams, &'static AddressParams)>> = Mutex::new(Vec::new());
+
+impl CustomParams {
+    // The static address parameters, leaked once per custom network
+    fn address_params(&self) -> &'static AddressParams {
+        let mut cache = CUSTOM_PARAMS.lock().expect("Poisoned address params");
+        if let Some(&(_, params)) = cache.iter().find(|&&(ref custom, _)| custom == self) {
+            return params;
+        }
+        let params: &'static AddressParams = Box::leak(Box::new(AddressParams {
+            p2pkh_prefix: self.p2pkh_prefix,
+            p2sh_prefix: self.p2sh_prefix,
+            blinded_prefix: self.blinded_prefix,
+            bech_hrp: Box::leak(self.bech_hrp.clone().into_boxed_str()),
+            blech_hrp: Box::leak(self.blech_hrp.clone().into_boxed_str()),
+        }));
+        cache.push((self.clone(), params));
+        params
+    }
+}
+
+/// Known Elements networks.
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    ElementsRegtest,
+    Liquid,
+    LiquidTestnet,
+    Custom(CustomParams),
+}
+
+impl Default for Network {
+    fn default() -> Network {
+        Network::ElementsRegtest
+    }
+}
+
+impl Network {
+    /// The address parameters of the network.
+    /// The parameters of a custom network are leaked once to get a static
+    /// reference as required by `elements::Address`.
+    pub fn address_params(&self) -> &'static AddressParams {
+        match *self {
+            Network::ElementsRegtest => &AddressParams::ELEMENTS,
+            Network::Liquid => &AddressParams::LIQUID,
+            Network::LiquidTestnet => &LIQUID_TESTNET,
+            Network::Custom(ref params) => params.address_params(),
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Network::ElementsRegtest => f.write_str("elementsregtest"),
+            Network::Liquid => f.write_str("liquid"),
+            Network::LiquidTestnet => f.write_str("liquidtestnet"),
+            Network::Custom(ref params) => write!(f, "custom({})", params.bech_hrp),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use elements::Address;
+
+    #[test]
+    fn test_network_serde() {
+        let custom = Network::Custom(CustomParams {
+            p2pkh_prefix: 235,
+            p2sh_prefix: 75,
+            blinded_prefix: 4,
+            bech_hrp: "ert".to_owned(),
+            blech_hrp: "el".to_owned(),
+        });
+        let yaml = serde_yaml::to_string(&custom).unwrap();
+        assert_eq!(serde_yaml::from_str::<Network>(&yaml).unwrap(), custom);
+        assert_eq!(custom.address_params(), &AddressParams::ELEMENTS);
+        // The custom parameters are only leaked once
+        assert!(::std::ptr::eq(
+            custom.address_params(),
+            custom.address_params()
+        ));
+
+        let liquid: Network = serde_yaml::from_str("liquidtestnet").unwrap();
+        assert_eq!(liquid, Network::LiquidTestnet);
+
+        // A regtest address is rejected on liquid testnet
+        let addr = "ert1qqqqsyqcyq5rqwzqfpg9scrgwpugpzysn9hc38w";
+        assert!(
+            Address::parse_with_params(addr, Network::ElementsRegtest.address_params()).is_ok()
+        );
+        assert!(Address::parse_with_params(addr, liquid.address_params()).is_err());
+    }
+}
@@ -0,0 +1,674 @@
+//! Atomic swaps of opt tokens for a premium
+//!
+//! The seller offers a utxo of opt tokens by signing its input with
+//! `SINGLE|ANYONECANPAY`. The signature commits to the token input and to the
+//! output at the same index paying the premium, and to nothing else. The buyer
+//! adds the output receiving the tokens and the inputs paying the premium and
+//! the fee, then signs their inputs with `ALL`. Neither leg can be taken without
+//! the other, so the tokens change hands without a trusted exchange.
+//!
+//! The offered tokens and the premium are explicit so that the buyer can check
+//! the offer, and because the seller cannot blind an output balanced by inputs
+//! added later. The outputs of the buyer can be blinded.
+//!
+//! Writing and selling an option is a single transaction: the writer funds the
+//! deposit of the contract and the tokens sent to the buyer, the buyer funds the
+//! premium paid to the writer. Both sign with `ALL` once the three legs are
+//! present, so neither the collateral nor the premium moves without the tokens.
+use bitcoin::PublicKey;
+use elements::pset::{self, PartiallySignedTransaction as Pset};
+use elements::secp256k1_zkp::{Message, Secp256k1};
+use elements::sighash::SigHashCache;
+use elements::{confidential, Address, AddressParams, AssetId, SigHashType, Transaction, TxOut};
+use serde::Serialize;
+
+use contract::{addr_output, set_output_value, txout, varint_len, Error, OptionContract};
+use funding::{self, Utxo};
+use verify::parse_rawsig;
+
+/// The sighash type of the seller signature
+pub const OFFER_SIGHASH: SigHashType = SigHashType::SinglePlusAnyoneCanPay;
+
+/// The position of the deposit output of a write-and-sell transaction
+const DEPOSIT_INDEX: usize = 0;
+/// The position of the output sending the tokens to the buyer
+const TOKENS_INDEX: usize = 1;
+/// The position of the output paying the premium to the writer
+const PREMIUM_INDEX: usize = 2;
+
+/// The tokens and the premium of an offer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OfferTerms {
+    pub token: AssetId,
+    /// The number of tokens sold
+    pub units: u64,
+    pub premium_asset: AssetId,
+    /// The premium in sats of the premium asset
+    pub premium: u64,
+}
+
+// An empty version 2 pset
+fn empty_pset() -> Pset {
+    Pset::from_tx(Transaction {
+        version: 2,
+        lock_time: 0,
+        input: vec![],
+        output: vec![],
+    })
+}
+
+// The explicit asset and value of the output
+fn explicit_txout(out: &TxOut) -> Option<(AssetId, u64)> {
+    match (out.asset, out.value) {
+        (confidential::Asset::Explicit(asset), confidential::Value::Explicit(value)) => {
+            Some((asset, value))
+        }
+        _ => None,
+    }
+}
+
+/// Creates the offer selling all the tokens of the explicit `token_utxo` for
+/// `premium` sats of `premium_asset` paid to `premium_addr`. The input must be
+/// signed by the seller with `SINGLE|ANYONECANPAY`.
+pub fn sell_offer(
+    token_utxo: &Utxo,
+    premium: u64,
+    premium_asset: AssetId,
+    premium_addr: &Address,
+) -> Result<Pset, Error> {
+    let blinded = token_utxo
+        .asset_blinder
+        .iter()
+        .chain(token_utxo.value_blinder.iter())
+        .any(|bf| !bf.is_zero());
+    if blinded {
+        return Err(Error::InvalidUtxo(
+            token_utxo.outpoint(),
+            "offered tokens must be explicit",
+        ));
+    }
+    let mut pset = empty_pset();
+    let mut inp = pset::Input::from_prevout(token_utxo.outpoint());
+    inp.witness_utxo = Some(token_utxo.txout()?);
+    inp.sighash_type = Some(OFFER_SIGHASH);
+    pset.add_input(inp);
+    // The premium output is explicit even to a confidential address
+    let premium_out = txout(premium_addr.script_pubkey(), premium, premium_asset);
+    pset.add_output(pset::Output::from_txout(premium_out));
+    Ok(pset)
+}
+
+/// The terms of the offer pset: the explicit tokens of its only input and the
+/// explicit premium of its only output
+pub fn offer_terms(offer: &Pset) -> Result<OfferTerms, Error> {
+    if offer.inputs.len() != 1 || offer.outputs.len() != 1 {
+        return Err(Error::InvalidOffer(
+            "the offer must have one token input and one premium output",
+        ));
+    }
+    let (token, units) = offer.inputs[0]
+        .witness_utxo
+        .as_ref()
+        .and_then(explicit_txout)
+        .ok_or(Error::InvalidOffer("the offered tokens are not explicit"))?;
+    let (premium_asset, premium) = explicit_txout(&offer.outputs[0].to_txout())
+        .ok_or(Error::InvalidOffer("the premium is not explicit"))?;
+    Ok(OfferTerms {
+        token,
+        units,
+        premium_asset,
+        premium,
+    })
+}
+
+// Check that the seller signed the token input with SINGLE|ANYONECANPAY, so
+// that the buyer can add inputs and outputs, and that the signatures commit to
+// the token input and the premium output of the offer
+fn check_offer_sigs(offer: &Pset) -> Result<(), Error> {
+    let inp = &offer.inputs[0];
+    let utxo = inp
+        .witness_utxo
+        .as_ref()
+        .ok_or(Error::InvalidOffer("the offered tokens are not explicit"))?;
+    // The keys of a finalized input are only known for p2wpkh witnesses
+    let sigs: Vec<(PublicKey, Vec<u8>)> = match inp.final_script_witness {
+        Some(ref witness) if witness.len() == 2 => {
+            let pk = PublicKey::from_slice(&witness[1])
+                .map_err(|_| Error::InvalidOffer("the finalized token input is not p2wpkh"))?;
+            vec![(pk, witness[0].clone())]
+        }
+        Some(_) => {
+            return Err(Error::InvalidOffer(
+                "the finalized token input is not p2wpkh",
+            ))
+        }
+        None => inp
+            .partial_sigs
+            .iter()
+            .map(|(pk, sig)| (*pk, sig.clone()))
+            .collect(),
+    };
+    if sigs.is_empty() {
+        return Err(Error::InvalidOffer("the token input is not signed"));
+    }
+
+    let tx = offer.extract_tx()?;
+    let mut sighash_cache = SigHashCache::new(&tx);
+    let secp = Secp256k1::verification_only();
+    for (pk, rawsig) in sigs {
+        let (sig, sighash_type) = parse_rawsig(&rawsig).ok_or(Error::InvalidOffer(
+            "the signature of the token input is malformed",
+        ))?;
+        if sighash_type != OFFER_SIGHASH {
+            return Err(Error::InvalidOffer(
+                "the token input is not signed with SINGLE|ANYONECANPAY",
+            ));
+        }
+        let params = &AddressParams::ELEMENTS;
+        let script_code = match inp.witness_script {
+            Some(ref script) if utxo.script_pubkey == script.to_v0_p2wsh() => script.clone(),
+            None if utxo.script_pubkey == Address::p2wpkh(&pk, None, params).script_pubkey() => {
+                Address::p2pkh(&pk, None, params).script_pubkey()
+            }
+            _ => {
+                return Err(Error::InvalidOffer(
+                    "the signing key does not own the token input",
+                ))
+            }
+        };
+        let sighash = sighash_cache.segwitv0_sighash(0, &script_code, utxo.value, OFFER_SIGHASH);
+        let msg = Message::from_slice(&sighash[..]).expect("32 byte sighash");
+        if secp.verify(&msg, &sig, &pk.key).is_err() {
+            return Err(Error::InvalidOffer(
+                "the signature of the token input is invalid",
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Completes the signed offer of `opt_token`: adds the output sending the tokens
+/// to `token_addr` after the premium output and funds the premium and the fee
+/// from the utxos at `fee_rate` sat/vB. The returned pset must be signed by the buyer.
+pub fn accept_offer(
+    offer: Pset,
+    opt_token: AssetId,
+    utxos: &[Utxo],
+    token_addr: &Address,
+    change: &Address,
+    btc_asset: AssetId,
+    fee_rate: f64,
+) -> Result<(Pset, OfferTerms), Error> {
+    let terms = offer_terms(&offer)?;
+    if terms.token != opt_token {
+        return Err(Error::InvalidOffer(
+            "the offered tokens are not the opt tokens of the contract",
+        ));
+    }
+    check_offer_sigs(&offer)?;
+
+    // The token input of the seller funds the token output
+    let prevout = offer.extract_tx()?.input[0].previous_output;
+    let seller_in = &offer.inputs[0];
+    let satisfaction_weight = seller_in.final_script_witness.as_ref().map(|witness| {
+        let script_sig_len = seller_in.final_script_sig.as_ref().map_or(0, |s| s.len());
+        script_sig_len * 4
+            + varint_len(witness.len())
+            + witness
+                .iter()
+                .map(|w| varint_len(w.len()) + w.len())
+                .sum::<usize>()
+    });
+    let seller_utxo = Utxo {
+        txid: prevout.txid,
+        vout: prevout.vout,
+        asset: terms.token,
+        value: terms.units,
+        asset_blinder: None,
+        value_blinder: None,
+        script_pubkey: seller_in
+            .witness_utxo
+            .as_ref()
+            .map(|out| out.script_pubkey.clone())
+            .expect("Explicit token input"),
+        max_satisfaction_weight: satisfaction_weight,
+    };
+    let mut funding_utxos = vec![seller_utxo];
+    funding_utxos.extend(utxos.iter().filter(|u| u.outpoint() != prevout).cloned());
+
+    let mut pset = offer;
+    pset.add_output(addr_output(token_addr, terms.units, terms.token));
+    let mut pset = funding::fund_pset(pset, &funding_utxos, change, btc_asset, fee_rate, 0)?;
+    // The outputs are blinded by the wallet of the buyer, which owns the inputs after the seller's
+    for out in pset
+        .outputs
+        .iter_mut()
+        .filter(|out| out.blinder_index.is_some())
+    {
+        out.blinder_index = Some(1);
+    }
+    Ok((pset, terms))
+}
+
+/// The legs of a write-and-sell transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct WriteSellTerms {
+    pub locked_asset: AssetId,
+    /// The amount in sats of locked asset deposited in the covenant
+    pub required_amount: u64,
+    /// The tokens sent to the buyer and the premium paid to the writer
+    #[serde(flatten)]
+    pub offer: OfferTerms,
+}
+
+/// Creates the writer leg of the transaction writing the contract and selling all
+/// its opt tokens to `buyer_addr` for `premium` sats of `premium_asset` paid to
+/// `premium_addr`. The deposit and the tokens are funded from the utxos of the
+/// writer at `fee_rate` sat/vB. The premium output is left to the buyer to fund.
+pub fn write_and_sell(
+    contract: &OptionContract,
+    utxos: &[Utxo],
+    buyer_addr: &Address,
+    premium: u64,
+    premium_asset: AssetId,
+    premium_addr: &Address,
+    change: &Address,
+    fee_rate: f64,
+) -> Result<Pset, Error> {
+    let locked = contract.locked_asset_params();
+    let opt_token = contract.claim_asset_params().opt_tkn();
+    let deposit_spk = contract.deposit_desc()?.script_pubkey();
+
+    let mut partial = empty_pset();
+    partial.add_output(pset::Output::from_txout(txout(
+        deposit_spk,
+        locked.value(),
+        locked.asset(),
+    )));
+    // The tokens are explicit so that the buyer can check them
+    partial.add_output(pset::Output::from_txout(txout(
+        buyer_addr.script_pubkey(),
+        contract.units(),
+        opt_token,
+    )));
+    let mut pset = funding::fund_pset(partial, utxos, change, contract.btc_asset(), fee_rate, 0)?;
+
+    let premium_out = txout(premium_addr.script_pubkey(), premium, premium_asset);
+    pset.add_output(pset::Output::from_txout(premium_out));
+    pset.outputs[PREMIUM_INDEX..].rotate_right(1);
+    Ok(pset)
+}
+
+/// Checks that the transaction has the three legs of writing and selling the
+/// contract: the deposit of the collateral, all the opt tokens and an explicit
+/// premium at their positions. Returns the terms of the legs.
+pub fn write_sell_terms(pset: &Pset, contract: &OptionContract) -> Result<WriteSellTerms, Error> {
+    if pset.outputs.len() <= PREMIUM_INDEX {
+        return Err(Error::InvalidOffer(
+            "missing the deposit, token or premium output",
+        ));
+    }
+    let out = |i: usize| pset.outputs[i].to_txout();
+    let locked = contract.locked_asset_params();
+    let deposit = out(DEPOSIT_INDEX);
+    if deposit.script_pubkey != contract.deposit_desc()?.script_pubkey()
+        || explicit_txout(&deposit) != Some((locked.asset(), locked.value()))
+    {
+        return Err(Error::InvalidOffer(
+            "the first output is not the deposit of the contract",
+        ));
+    }
+    let opt_token = contract.claim_asset_params().opt_tkn();
+    if explicit_txout(&out(TOKENS_INDEX)) != Some((opt_token, contract.units())) {
+        return Err(Error::InvalidOffer(
+            "the second output does not send all the opt tokens",
+        ));
+    }
+    let (premium_asset, premium) = explicit_txout(&out(PREMIUM_INDEX))
+        .ok_or(Error::InvalidOffer("the premium is not explicit"))?;
+    Ok(WriteSellTerms {
+        locked_asset: locked.asset(),
+        required_amount: locked.value(),
+        offer: OfferTerms {
+            token: opt_token,
+            units: contract.units(),
+            premium_asset,
+            premium,
+        },
+    })
+}
+
+/// Checks the write-and-sell pset completed by the buyer against `writer_leg`,
+/// created by `write_and_sell`, before the writer signs it with `ALL`: the legs,
+/// the premium paid to `premium_addr`, the inputs and the outputs of the writer
+/// leg unchanged at their positions, and the fee output not less than the fee of
+/// the writer leg. Returns the terms of the legs.
+pub fn check_write_sell(
+    pset: &Pset,
+    writer_leg: &Pset,
+    contract: &OptionContract,
+    premium_addr: &Address,
+) -> Result<WriteSellTerms, Error> {
+    let terms = write_sell_terms(pset, contract)?;
+    if pset.outputs[PREMIUM_INDEX].to_txout().script_pubkey != premium_addr.script_pubkey() {
+        return Err(Error::InvalidOffer(
+            "the premium is not paid to the premium address",
+        ));
+    }
+    let writer_inputs = writer_leg.extract_tx()?.input;
+    let inputs = pset.extract_tx()?.input;
+    if inputs.len() < writer_inputs.len()
+        || writer_inputs
+            .iter()
+            .zip(&inputs)
+            .any(|(writer_in, inp)| writer_in.previous_output != inp.previous_output)
+    {
+        return Err(Error::InvalidOffer("the inputs of the writer are changed"));
+    }
+    let btc = contract.btc_asset();
+    let writer_fee_index = fee_index(writer_leg, btc)?;
+    for (i, writer_out) in writer_leg.outputs.iter().enumerate() {
+        if i == writer_fee_index {
+            continue;
+        }
+        if pset.outputs.get(i).map(|out| out.to_txout()) != Some(writer_out.to_txout()) {
+            return Err(Error::InvalidOffer("the outputs of the writer are changed"));
+        }
+    }
+    // The buyer adds its part of the fee to the fee output of the writer
+    let fee = |pset: &Pset, i: usize| {
+        pset.outputs
+            .get(i)
+            .and_then(|out| explicit_txout(&out.to_txout()))
+            .filter(|&(asset, _)| asset == btc)
+            .map(|(_, value)| value)
+    };
+    match (
+        fee(pset, writer_fee_index),
+        fee(writer_leg, writer_fee_index),
+    ) {
+        (Some(fee), Some(writer_fee)) if fee >= writer_fee => Ok(terms),
+        _ => Err(Error::InvalidOffer("the fee of the writer is reduced")),
+    }
+}
+
+// The position of the explicit fee output of the pset
+fn fee_index(pset: &Pset, btc_asset: AssetId) -> Result<usize, Error> {
+    pset.outputs
+        .iter()
+        .position(|out| is_fee(&out.to_txout(), btc_asset))
+        .ok_or(Error::MissingFeeOutput)
+}
+
+fn is_fee(out: &TxOut, btc_asset: AssetId) -> bool {
+    out.script_pubkey.is_empty() && out.asset == confidential::Asset::Explicit(btc_asset)
+}
+
+/// Completes the writer leg with the buyer leg: checks the legs and that the
+/// tokens are sent to `token_addr`, then funds the premium and the fee of the
+/// buyer leg from the utxos at `fee_rate` sat/vB. The inputs and the change of
+/// the buyer follow the writer's and the fees are paid by a single fee output.
+/// The returned pset must be signed by both the writer and the buyer.
+pub fn take_write_sell(
+    writer_leg: Pset,
+    contract: &OptionContract,
+    utxos: &[Utxo],
+    token_addr: &Address,
+    change: &Address,
+    fee_rate: f64,
+) -> Result<(Pset, WriteSellTerms), Error> {
+    let terms = write_sell_terms(&writer_leg, contract)?;
+    if writer_leg.outputs[TOKENS_INDEX].to_txout().script_pubkey != token_addr.script_pubkey() {
+        return Err(Error::InvalidOffer("the tokens are not sent to the buyer"));
+    }
+
+    let premium_out = writer_leg.outputs[PREMIUM_INDEX].to_txout();
+    let mut partial = empty_pset();
+    partial.add_output(pset::Output::from_txout(premium_out));
+    let buyer_leg = funding::fund_pset(partial, utxos, change, contract.btc_asset(), fee_rate, 0)?;
+
+    let btc = contract.btc_asset();
+    let mut pset = writer_leg;
+    let fee_index = fee_index(&pset, btc)?;
+    let mut fee = explicit_txout(&pset.outputs[fee_index].to_txout())
+        .ok_or(Error::MissingFeeOutput)?
+        .1;
+    // The outputs of the buyer are blinded by the wallet owning the first buyer input
+    let buyer_blinder = pset.inputs.len() as u32;
+    for inp in buyer_leg.inputs {
+        pset.add_input(inp);
+    }
+    for mut out in buyer_leg.outputs.into_iter().skip(1) {
+        let txout = out.to_txout();
+        if is_fee(&txout, btc) {
+            fee += explicit_txout(&txout).ok_or(Error::MissingFeeOutput)?.1;
+            continue;
+        }
+        if out.blinder_index.is_some() {
+            out.blinder_index = Some(buyer_blinder);
+        }
+        pset.add_output(out);
+    }
+    set_output_value(&mut pset, fee_index, fee);
+    Ok((pset, terms))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{new_private_key, BTC_ASSET, CTRL_PK, CTRL_SK};
+    use contract::test_contract;
+    use elements::Script;
+    use std::str::FromStr;
+    use test_utils::test_utxo;
+
+    #[test]
+    fn test_offer() {
+        let btc = AssetId::from_slice(&BTC_ASSET).unwrap();
+        let usd = AssetId::from_slice(&[1u8; 32]).unwrap();
+        let opt_token = AssetId::from_slice(&[2u8; 32]).unwrap();
+        let pk = bitcoin::PublicKey::from_str(CTRL_PK).unwrap();
+        let addr = Address::p2wpkh(&pk, None, &AddressParams::ELEMENTS);
+
+        let mut tokens = test_utxo(0, opt_token, 10);
+        tokens.script_pubkey = addr.script_pubkey();
+        let mut offer = sell_offer(&tokens, 500_000, usd, &addr).unwrap();
+        assert_eq!(offer.inputs[0].sighash_type, Some(OFFER_SIGHASH));
+        assert_eq!(
+            offer_terms(&offer).unwrap(),
+            OfferTerms {
+                token: opt_token,
+                units: 10,
+                premium_asset: usd,
+                premium: 500_000,
+            }
+        );
+
+        let utxos = vec![test_utxo(1, usd, 800_000), test_utxo(2, btc, 100_000)];
+        assert!(accept_offer(offer.clone(), usd, &utxos, &addr, &addr, btc, 1.0).is_err());
+        match accept_offer(offer.clone(), opt_token, &utxos, &addr, &addr, btc, 1.0) {
+            Err(Error::InvalidOffer(reason)) => assert_eq!(reason, "the token input is not signed"),
+            r => panic!("Expected an unsigned offer, got {:?}", r.map(|r| r.1)),
+        }
+
+        // The seller signs the token input with its p2wpkh script code
+        let secp = Secp256k1::signing_only();
+        let sign = |offer: &Pset, sk: &bitcoin::PrivateKey, sighash_type: SigHashType| {
+            let tx = offer.extract_tx().unwrap();
+            let script_code = Address::p2pkh(&pk, None, &AddressParams::ELEMENTS).script_pubkey();
+            let value = confidential::Value::Explicit(10);
+            let sighash =
+                SigHashCache::new(&tx).segwitv0_sighash(0, &script_code, value, sighash_type);
+            let msg = Message::from_slice(&sighash[..]).unwrap();
+            let mut rawsig = secp.sign(&msg, &sk.key).serialize_der().to_vec();
+            rawsig.push(sighash_type.as_u32() as u8);
+            rawsig
+        };
+        let sk = bitcoin::PrivateKey::from_wif(CTRL_SK).unwrap();
+        let rawsig = sign(&offer, &sk, SigHashType::All);
+        offer.inputs[0].partial_sigs.insert(pk, rawsig);
+        match accept_offer(offer.clone(), opt_token, &utxos, &addr, &addr, btc, 1.0) {
+            Err(Error::InvalidOffer(reason)) => assert_eq!(
+                reason,
+                "the token input is not signed with SINGLE|ANYONECANPAY"
+            ),
+            r => panic!("Expected a sighash error, got {:?}", r.map(|r| r.1)),
+        }
+
+        // A signature of another key, or of another premium, is rejected
+        let rawsig = sign(&offer, &new_private_key(), OFFER_SIGHASH);
+        offer.inputs[0].partial_sigs.insert(pk, rawsig);
+        match accept_offer(offer.clone(), opt_token, &utxos, &addr, &addr, btc, 1.0) {
+            Err(Error::InvalidOffer(reason)) => {
+                assert_eq!(reason, "the signature of the token input is invalid")
+            }
+            r => panic!("Expected an invalid signature, got {:?}", r.map(|r| r.1)),
+        }
+        let rawsig = sign(&offer, &sk, OFFER_SIGHASH);
+        let mut tampered = offer.clone();
+        tampered.inputs[0].partial_sigs.insert(pk, rawsig.clone());
+        set_output_value(&mut tampered, 0, 400_000);
+        match accept_offer(tampered, opt_token, &utxos, &addr, &addr, btc, 1.0) {
+            Err(Error::InvalidOffer(reason)) => {
+                assert_eq!(reason, "the signature of the token input is invalid")
+            }
+            r => panic!("Expected an invalid signature, got {:?}", r.map(|r| r.1)),
+        }
+
+        offer.inputs[0].partial_sigs.insert(pk, rawsig);
+        let (pset, terms) = accept_offer(offer, opt_token, &utxos, &addr, &addr, btc, 1.0).unwrap();
+        assert_eq!(terms.units, 10);
+        // The seller input and the premium output keep their index
+        let tx = pset.extract_tx().unwrap();
+        assert_eq!(tx.input[0].previous_output, tokens.outpoint());
+        assert_eq!(tx.output[0].value, confidential::Value::Explicit(500_000));
+        assert_eq!(tx.output[1].asset, confidential::Asset::Explicit(opt_token));
+        assert_eq!(tx.output[1].value, confidential::Value::Explicit(10));
+        assert_eq!(tx.input.len(), 3);
+    }
+
+    #[test]
+    fn test_write_and_sell() {
+        let contract = test_contract();
+        let btc = AssetId::from_slice(&BTC_ASSET).unwrap();
+        let usd = AssetId::from_slice(&[1u8; 32]).unwrap();
+        let opt_token = contract.claim_asset_params().opt_tkn();
+        let pk = bitcoin::PublicKey::from_str(CTRL_PK).unwrap();
+        let writer_addr = Address::p2wpkh(&pk, None, &AddressParams::ELEMENTS);
+        let buyer_addr = Address::p2sh(&Script::new(), None, &AddressParams::ELEMENTS);
+
+        let writer_utxos = vec![test_utxo(0, btc, 150_000_000), test_utxo(1, opt_token, 1)];
+        let writer_leg = write_and_sell(
+            &contract,
+            &writer_utxos,
+            &buyer_addr,
+            500_000,
+            usd,
+            &writer_addr,
+            &writer_addr,
+            1.0,
+        )
+        .unwrap();
+        let terms = write_sell_terms(&writer_leg, &contract).unwrap();
+        assert_eq!(terms.required_amount, 100_000_000);
+        assert_eq!(terms.offer.units, 1);
+        assert_eq!(terms.offer.premium, 500_000);
+
+        let buyer_utxos = vec![test_utxo(2, usd, 1_000_000), test_utxo(3, btc, 100_000)];
+        match take_write_sell(
+            writer_leg.clone(),
+            &contract,
+            &buyer_utxos,
+            &writer_addr,
+            &buyer_addr,
+            1.0,
+        ) {
+            Err(Error::InvalidOffer(reason)) => {
+                assert_eq!(reason, "the tokens are not sent to the buyer")
+            }
+            r => panic!(
+                "Expected the tokens to another address, got {:?}",
+                r.map(|r| r.1)
+            ),
+        }
+        let (pset, taken) = take_write_sell(
+            writer_leg.clone(),
+            &contract,
+            &buyer_utxos,
+            &buyer_addr,
+            &buyer_addr,
+            1.0,
+        )
+        .unwrap();
+        assert_eq!(taken, terms);
+        assert_eq!(write_sell_terms(&pset, &contract).unwrap(), terms);
+        assert_eq!(
+            check_write_sell(&pset, &writer_leg, &contract, &writer_addr).unwrap(),
+            terms
+        );
+
+        // The writer rejects a premium paid to another script or a reduced change
+        let check_tampered = |tampered: &Pset| match check_write_sell(
+            tampered,
+            &writer_leg,
+            &contract,
+            &writer_addr,
+        ) {
+            Err(Error::InvalidOffer(reason)) => reason,
+            r => panic!("Expected a tampered pset, got {:?}", r),
+        };
+        let mut tampered = pset.clone();
+        let mut premium_out = tampered.outputs[PREMIUM_INDEX].to_txout();
+        premium_out.script_pubkey = buyer_addr.script_pubkey();
+        tampered.outputs[PREMIUM_INDEX] = pset::Output::from_txout(premium_out);
+        assert_eq!(
+            check_tampered(&tampered),
+            "the premium is not paid to the premium address"
+        );
+        let writer_fee_index = fee_index(&writer_leg, btc).unwrap();
+        let change_index = (PREMIUM_INDEX + 1..writer_leg.outputs.len())
+            .find(|&i| i != writer_fee_index)
+            .expect("Writer change");
+        let mut tampered = pset.clone();
+        let change = explicit_txout(&tampered.outputs[change_index].to_txout())
+            .unwrap()
+            .1;
+        set_output_value(&mut tampered, change_index, change - 1_000);
+        let fee = explicit_txout(&tampered.outputs[writer_fee_index].to_txout())
+            .unwrap()
+            .1;
+        set_output_value(&mut tampered, writer_fee_index, fee + 1_000);
+        assert_eq!(
+            check_tampered(&tampered),
+            "the outputs of the writer are changed"
+        );
+        let mut tampered = pset.clone();
+        set_output_value(&mut tampered, writer_fee_index, 0);
+        assert_eq!(
+            check_tampered(&tampered),
+            "the fee of the writer is reduced"
+        );
+
+        // The legs are balanced and share one fee output
+        let tx = pset.extract_tx().unwrap();
+        assert_eq!(tx.input.len(), 4);
+        assert_eq!(
+            tx.output
+                .iter()
+                .filter(|out| out.script_pubkey.is_empty())
+                .count(),
+            1
+        );
+        let total = |asset| {
+            tx.output
+                .iter()
+                .filter_map(|out| explicit_txout(out))
+                .filter(|&(a, _)| a == asset)
+                .map(|(_, v)| v)
+                .sum::<u64>()
+        };
+        assert_eq!(total(btc), 150_100_000);
+        assert_eq!(total(usd), 1_000_000);
+        assert_eq!(total(opt_token), 1);
+    }
+}
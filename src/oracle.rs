@@ -0,0 +1,143 @@
+//! Price attestations of the oracle of cash-settled options
+//!
+//! Instead of exercising the option by paying the strike, cash-settled options
+//! split the collateral between the opt and bene token holders according to the
+//! payoff at the price attested by an oracle. The oracle signs a message
+//! committing to the underlying and quote assets, the underlying amount, the
+//! expiry and the price. The covenant checks the signature with
+//! `OP_CHECKSIGFROMSTACK`, which verifies an ecdsa signature of the sha256 of the
+//! message, through the `csfs` fragment. There is a settlement branch for each
+//! price of the settlement grid, so the oracle observes the price at expiry and
+//! attests the nearest grid price, see `grid_price`. Both sides settle at the
+//! grid price, so any observed price settles the option.
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::{PrivateKey, PublicKey};
+use elements::secp256k1_zkp::{Message, Secp256k1, Signature};
+use elements::AssetId;
+
+/// The tag prefixed to all the price messages
+const PRICE_TAG: &[u8] = b"options-elements/price";
+
+/// Blocks after a height expiry before the writer can claim the collateral of
+/// a cash-settled option that the oracle did not settle
+pub const ORACLE_TIMEOUT_BLOCKS: u32 = 144;
+/// Seconds after a timestamp expiry before the writer can claim the collateral
+/// of a cash-settled option that the oracle did not settle
+pub const ORACLE_TIMEOUT_SECS: u32 = 86400;
+
+/// The settlement terms of a cash-settled option
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CashSettlement {
+    /// The key of the oracle attesting the price at expiry
+    pub oracle_pk: PublicKey,
+    /// The prices the option can be settled at, in sats of the quote asset for
+    /// the underlying amount
+    pub prices: Vec<u64>,
+    /// Whether the option is a put, where the quote asset is locked
+    pub put: bool,
+    /// The attested price to settle at
+    pub price: Option<u64>,
+    /// The oracle signature of the price message of `price`
+    pub oracle_sig: Option<Signature>,
+}
+
+impl CashSettlement {
+    pub fn new(oracle_pk: PublicKey, prices: Vec<u64>, put: bool) -> Self {
+        CashSettlement {
+            oracle_pk,
+            prices,
+            put,
+            price: None,
+            oracle_sig: None,
+        }
+    }
+}
+
+/// The price of the settlement grid `prices` that the observed `price` settles
+/// at: the nearest grid price, or the lower one of two equally near grid prices.
+/// None for an empty grid
+pub fn grid_price(prices: &[u64], price: u64) -> Option<u64> {
+    prices.iter().cloned().min_by_key(|&grid| {
+        let distance = if grid > price {
+            grid - price
+        } else {
+            price - grid
+        };
+        (distance, grid)
+    })
+}
+
+/// The message attesting that `underlying_amount` of the underlying asset is
+/// worth `price` sats of the quote asset at `expiry`
+pub fn price_message(
+    underlying: AssetId,
+    underlying_amount: u64,
+    quote: AssetId,
+    expiry: u32,
+    price: u64,
+) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    engine.input(PRICE_TAG);
+    engine.input(&underlying.into_inner().into_inner());
+    engine.input(&underlying_amount.to_be_bytes());
+    engine.input(&quote.into_inner().into_inner());
+    engine.input(&expiry.to_be_bytes());
+    engine.input(&price.to_be_bytes());
+    sha256::Hash::from_engine(engine)
+}
+
+// The signed digest of the message, as hashed by OP_CHECKSIGFROMSTACK
+fn csfs_digest(msg: &sha256::Hash) -> Message {
+    let digest = sha256::Hash::hash(&msg[..]);
+    Message::from_slice(&digest[..]).expect("32 byte digest")
+}
+
+/// Signs the price message with the oracle key. Used as a local oracle for testing
+pub fn sign_price(oracle_sk: &PrivateKey, msg: &sha256::Hash) -> Signature {
+    let secp = Secp256k1::signing_only();
+    secp.sign(&csfs_digest(msg), &oracle_sk.key)
+}
+
+/// Whether `sig` is a valid oracle signature of the price message
+pub fn verify_price(oracle_pk: &PublicKey, msg: &sha256::Hash, sig: &Signature) -> bool {
+    let secp = Secp256k1::verification_only();
+    secp.verify(&csfs_digest(msg), sig, &oracle_pk.key).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{BTC_ASSET, CTRL_PK, CTRL_SK};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_price_attestation() {
+        let btc = AssetId::from_slice(&BTC_ASSET).unwrap();
+        let usd = AssetId::from_slice(&[1u8; 32]).unwrap();
+        let sk = PrivateKey::from_wif(CTRL_SK).unwrap();
+        let pk = PublicKey::from_str(CTRL_PK).unwrap();
+
+        let msg = price_message(btc, 100_000_000, usd, 1622332800, 60_000);
+        let sig = sign_price(&sk, &msg);
+        assert!(verify_price(&pk, &msg, &sig));
+        // The signature only attests the signed price
+        let other = price_message(btc, 100_000_000, usd, 1622332800, 60_001);
+        assert!(!verify_price(&pk, &other, &sig));
+        assert_ne!(
+            msg,
+            price_message(usd, 100_000_000, btc, 1622332800, 60_000)
+        );
+    }
+
+    #[test]
+    fn test_grid_price() {
+        let prices = [40_000, 50_000, 60_000];
+        assert_eq!(grid_price(&prices, 50_000), Some(50_000));
+        assert_eq!(grid_price(&prices, 56_000), Some(60_000));
+        // Ties settle at the lower price, prices off the grid at its bounds
+        assert_eq!(grid_price(&prices, 55_000), Some(50_000));
+        assert_eq!(grid_price(&prices, 1_000), Some(40_000));
+        assert_eq!(grid_price(&prices, 90_000), Some(60_000));
+        assert_eq!(grid_price(&[], 50_000), None);
+    }
+}
@@ -0,0 +1,275 @@
+//! Local store of the option contracts managed from one workspace
+//!
+//! Contracts are keyed by their deterministic contract ID, see
+//! `OptionContract::contract_id`, and record the terms, the deposit outpoint
+//! and the lifecycle state of the contract.
+use elements::{OutPoint, Txid};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+
+use config::Config;
+use contract::{Error, OptionContract, SpendType, EXERCISE_PAYOUT_VOUT, SUCCESSOR_VOUT};
+
+/// Whether the contract is a call or a put option
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OptionKind {
+    Call,
+    Put,
+}
+
+/// The lifecycle state of a contract
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContractState {
+    /// The deposit address was created, but the collateral is not known to be locked
+    Created,
+    /// The collateral is locked at the deposit outpoint
+    Funded,
+    Exercised,
+    Cancelled,
+    Expired,
+    /// The claim asset paid at exercise was claimed by the bene token holder
+    Claimed,
+}
+
+impl ContractState {
+    /// The state of the contract after it is spent with the operation
+    pub fn after_spend(ty: SpendType) -> ContractState {
+        match ty {
+            SpendType::Exercise => ContractState::Exercised,
+            SpendType::Cancel => ContractState::Cancelled,
+            SpendType::Expiry => ContractState::Expired,
+            SpendType::ClaimBene => ContractState::Claimed,
+        }
+    }
+}
+
+/// The strike paid by an exercise of the contract, which the bene token holders
+/// claim by burning the exercised tokens
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExercisePayout {
+    /// The output of the exercise transaction paying the strike
+    pub outpoint: OutPoint,
+    /// The number of tokens exercised
+    pub units: u64,
+    /// The number of tokens remaining after the exercise
+    pub remaining: u64,
+}
+
+/// A contract stored in the registry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContractEntry {
+    pub kind: OptionKind,
+    /// The expiry of the option, a block height or a unix timestamp as in `nLockTime`
+    pub expiry: u32,
+    /// The strike amount in sats of the claim asset
+    pub strike: u64,
+    /// The assets, tokens and keys of the contract
+    pub config: Config,
+    /// The outpoint of the covenant holding the collateral
+    pub deposit: Option<OutPoint>,
+    pub state: ContractState,
+    /// The number of option tokens exercised by partial exercises. The
+    /// collateral of the remaining tokens is locked in the successor covenant
+    #[serde(default)]
+    pub exercised_units: u64,
+    /// The payouts of the exercises that are not claimed yet
+    #[serde(default)]
+    pub payouts: Vec<ExercisePayout>,
+}
+
+impl ContractEntry {
+    /// Create a new entry for a contract that is not funded yet
+    pub fn new(kind: OptionKind, expiry: u32, strike: u64, config: Config) -> Self {
+        ContractEntry {
+            kind,
+            expiry,
+            strike,
+            config,
+            deposit: None,
+            state: ContractState::Created,
+            exercised_units: 0,
+            payouts: vec![],
+        }
+    }
+
+    /// The option contract with the terms of the entry as originally created
+    pub fn original_contract(&self) -> OptionContract {
+        match self.kind {
+            OptionKind::Call => OptionContract::from_cfg(&self.config, self.expiry, self.strike),
+            OptionKind::Put => OptionContract::put_from_cfg(&self.config, self.expiry, self.strike),
+        }
+    }
+
+    /// The option contract currently locking the collateral. This is the
+    /// successor covenant of the remaining tokens after partial exercises
+    pub fn contract(&self) -> Result<OptionContract, Error> {
+        let contract = self.original_contract();
+        if self.exercised_units == 0 {
+            return Ok(contract);
+        }
+        let remaining = contract.units().saturating_sub(self.exercised_units);
+        contract.successor(remaining)
+    }
+
+    /// The contract claiming the exercise payout with claimbene. The payout
+    /// covenant depends on the exercised and the remaining tokens of the exercise
+    pub fn payout_contract(&self, payout: &ExercisePayout) -> Result<OptionContract, Error> {
+        self.original_contract()
+            .successor(payout.units + payout.remaining)?
+            .with_exercise_units(payout.units)
+    }
+
+    /// The hex encoded contract ID of the entry. The ID does not change
+    /// with partial exercises
+    pub fn id(&self) -> Result<String, Error> {
+        Ok(self.original_contract().contract_id()?.to_string())
+    }
+
+    /// Record the spend of `contract` with the operation `ty` in the transaction `txid`
+    pub fn record_spend(&mut self, contract: &OptionContract, ty: SpendType, txid: Txid) {
+        let units = contract.exercise_units();
+        let remaining = contract.units().saturating_sub(units);
+        match ty {
+            SpendType::Exercise => {
+                self.payouts.push(ExercisePayout {
+                    outpoint: OutPoint::new(txid, EXERCISE_PAYOUT_VOUT),
+                    units,
+                    remaining,
+                });
+                if remaining > 0 {
+                    self.exercised_units += units;
+                    self.deposit = Some(OutPoint::new(txid, SUCCESSOR_VOUT));
+                } else {
+                    self.state = ContractState::Exercised;
+                }
+            }
+            SpendType::ClaimBene => {
+                self.payouts
+                    .retain(|payout| (payout.units, payout.remaining) != (units, remaining));
+                // Claiming a partial exercise does not end the contract
+                if self.state == ContractState::Exercised && self.payouts.is_empty() {
+                    self.state = ContractState::Claimed;
+                }
+            }
+            _ => self.state = ContractState::after_spend(ty),
+        }
+    }
+}
+
+/// The contracts of the workspace keyed by the hex encoded contract ID
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Registry {
+    contracts: BTreeMap<String, ContractEntry>,
+}
+
+impl Registry {
+    /// Read the YAML registry file at `path`. A missing file is an empty registry
+    pub fn from_file(path: &str) -> Result<Registry, Error> {
+        match File::open(path) {
+            Ok(file) => serde_yaml::from_reader(file).map_err(Error::MalformedRegistry),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Registry::default()),
+            Err(e) => Err(Error::RegistryFile(path.to_owned(), e)),
+        }
+    }
+
+    /// Write the registry as YAML to the file at `path`
+    pub fn to_file(&self, path: &str) -> Result<(), Error> {
+        let file = File::create(path).map_err(|e| Error::RegistryFile(path.to_owned(), e))?;
+        serde_yaml::to_writer(file, self).map_err(Error::MalformedRegistry)
+    }
+
+    /// Add the entry to the registry and return its contract ID.
+    /// An existing entry for the same contract is kept as it is, so registering
+    /// the same terms twice does not reset the lifecycle state.
+    pub fn insert(&mut self, entry: ContractEntry) -> Result<String, Error> {
+        let id = entry.id()?;
+        self.contracts.entry(id.clone()).or_insert(entry);
+        Ok(id)
+    }
+
+    /// Get the contract with the ID
+    pub fn get(&self, id: &str) -> Result<&ContractEntry, Error> {
+        self.contracts
+            .get(id)
+            .ok_or_else(|| Error::UnknownContract(id.to_owned()))
+    }
+
+    /// Get the contract with the ID for updating the deposit or the state
+    pub fn get_mut(&mut self, id: &str) -> Result<&mut ContractEntry, Error> {
+        self.contracts
+            .get_mut(id)
+            .ok_or_else(|| Error::UnknownContract(id.to_owned()))
+    }
+
+    /// Iterate over the contracts ordered by contract ID
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &ContractEntry)> {
+        self.contracts.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_utils::test_config;
+
+    #[test]
+    fn test_registry() {
+        let cfg = Config {
+            opt_token_supply: 4,
+            ..test_config()
+        };
+        let call = ContractEntry::new(OptionKind::Call, 1622332800, 40_000, cfg.clone());
+        let put = ContractEntry::new(OptionKind::Put, 1622332800, 40_000, cfg);
+
+        let mut registry = Registry::default();
+        let call_id = registry.insert(call.clone()).unwrap();
+        let put_id = registry.insert(put).unwrap();
+        assert_ne!(call_id, put_id);
+
+        registry.get_mut(&call_id).unwrap().state = ContractState::Funded;
+        // Registering the same terms again keeps the state
+        assert_eq!(registry.insert(call).unwrap(), call_id);
+        assert_eq!(registry.get(&call_id).unwrap().state, ContractState::Funded);
+
+        // A partial exercise moves the deposit to the successor covenant
+        let txid = Txid::default();
+        let mut entry = registry.get(&call_id).unwrap().clone();
+        let contract = entry.contract().unwrap().with_exercise_units(1).unwrap();
+        entry.record_spend(&contract, SpendType::Exercise, txid);
+        assert_eq!(entry.state, ContractState::Funded);
+        assert_eq!(entry.deposit, Some(OutPoint::new(txid, 2)));
+        assert_eq!(entry.contract().unwrap().units(), 3);
+        assert_eq!(entry.id().unwrap(), call_id);
+        let contract = entry.contract().unwrap();
+        entry.record_spend(&contract, SpendType::Exercise, txid);
+        assert_eq!(entry.state, ContractState::Exercised);
+
+        // Each payout is claimed with the tokens of its exercise
+        let payouts = entry.payouts.clone();
+        assert_eq!(payouts.len(), 2);
+        assert_eq!(payouts[0].outpoint, OutPoint::new(txid, 1));
+        assert_eq!((payouts[0].units, payouts[0].remaining), (1, 3));
+        assert_eq!((payouts[1].units, payouts[1].remaining), (3, 0));
+        let claim = entry.payout_contract(&payouts[1]).unwrap();
+        assert_eq!((claim.units(), claim.exercise_units()), (3, 3));
+        entry.record_spend(&claim, SpendType::ClaimBene, txid);
+        assert_eq!(entry.state, ContractState::Exercised);
+        assert_eq!(entry.payouts, vec![payouts[0].clone()]);
+        let claim = entry.payout_contract(&payouts[0]).unwrap();
+        assert_eq!((claim.units(), claim.exercise_units()), (4, 1));
+        entry.record_spend(&claim, SpendType::ClaimBene, txid);
+        assert_eq!(entry.state, ContractState::Claimed);
+
+        let yaml = serde_yaml::to_string(&registry).unwrap();
+        let registry: Registry = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(registry.iter().count(), 2);
+        match registry.get("00") {
+            Err(e @ Error::UnknownContract(..)) => assert_eq!(e.exit_code(), 2),
+            res => panic!("Expected unknown contract, got {:?}", res.map(|e| e.state)),
+        }
+    }
+}
@@ -0,0 +1,436 @@
+//! Minimal Elements JSON-RPC client
+//!
+//! Only supports the handful of wallet calls required to drive the contract
+//! spending pipeline: funding, converting to pset, blinding/signing and
+//! broadcasting. Requests are sent over plain HTTP/1.1 as done by elements-cli.
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::{error, fmt, fs, io};
+
+use elements::encode::serialize_hex;
+use elements::{Address, OutPoint};
+use serde_json::{json, Value};
+
+use contract::{
+    self, check_wallet_blinding, pset_from_base64, pset_to_base64, OptionContract, SpendType,
+};
+use verify;
+
+/// Authentication for the RPC server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Auth {
+    /// No authentication
+    None,
+    /// rpcuser/rpcpassword from elements.conf
+    UserPass(String, String),
+    /// Path to the `.cookie` file written by elementsd
+    CookieFile(PathBuf),
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The rpc url is not of the form http://host:port[/path]
+    InvalidUrl(String),
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// Non-json response from the server with the HTTP status line
+    Http(String),
+    /// Error returned by elementsd
+    Rpc {
+        code: i64,
+        message: String,
+    },
+    /// The rpc response is missing an expected field
+    UnexpectedResponse(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidUrl(ref url) => write!(f, "Invalid rpc url {}", url),
+            Error::Io(ref e) => write!(f, "Rpc connection error: {}", e),
+            Error::Json(ref e) => write!(f, "Rpc json error: {}", e),
+            Error::Http(ref status) => write!(f, "Unexpected http response: {}", status),
+            Error::Rpc { code, ref message } => write!(f, "Rpc error {}: {}", code, message),
+            Error::UnexpectedResponse(ref s) => write!(f, "Unexpected rpc response: {}", s),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+#[doc(hidden)]
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::Json(e)
+    }
+}
+
+/// A blocking client for the elementsd JSON-RPC interface
+#[derive(Debug, Clone)]
+pub struct Client {
+    /// host:port of the server
+    host: String,
+    /// The http path, used for selecting the wallet(/wallet/<name>)
+    path: String,
+    auth: Auth,
+}
+
+impl Client {
+    /// Create a new client for an url of the form http://host:port[/path]
+    pub fn new(url: &str, auth: Auth) -> Result<Self, Error> {
+        let rest = if url.starts_with("http://") {
+            &url["http://".len()..]
+        } else {
+            return Err(Error::InvalidUrl(url.to_owned()));
+        };
+        let (host, path) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, "/"),
+        };
+        if host.is_empty() {
+            return Err(Error::InvalidUrl(url.to_owned()));
+        }
+        Ok(Client {
+            host: host.to_owned(),
+            path: path.to_owned(),
+            auth,
+        })
+    }
+
+    /// Basic auth credentials
+    fn credentials(&self) -> Result<Option<String>, Error> {
+        match self.auth {
+            Auth::None => Ok(None),
+            Auth::UserPass(ref user, ref pass) => Ok(Some(base64::encode(
+                format!("{}:{}", user, pass).as_bytes(),
+            ))),
+            Auth::CookieFile(ref path) => {
+                let cookie = fs::read_to_string(path)?;
+                Ok(Some(base64::encode(cookie.trim().as_bytes())))
+            }
+        }
+    }
+
+    /// Call the rpc `method` with `params` and return the result field
+    pub fn call(&self, method: &str, params: &[Value]) -> Result<Value, Error> {
+        let body = serde_json::to_vec(&json!({
+            "jsonrpc": "1.0",
+            "id": "options-elements",
+            "method": method,
+            "params": params,
+        }))?;
+
+        let mut req = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n",
+            self.path,
+            self.host,
+            body.len()
+        );
+        if let Some(cred) = self.credentials()? {
+            req.push_str(&format!("Authorization: Basic {}\r\n", cred));
+        }
+        req.push_str("\r\n");
+
+        let mut stream = TcpStream::connect(&self.host)?;
+        stream.write_all(req.as_bytes())?;
+        stream.write_all(&body)?;
+        stream.flush()?;
+
+        let mut resp = Vec::new();
+        stream.read_to_end(&mut resp)?;
+        let resp = String::from_utf8_lossy(&resp);
+        let status = resp.lines().next().unwrap_or("").to_owned();
+        let resp_body = match resp.find("\r\n\r\n") {
+            Some(pos) => &resp[pos + 4..],
+            None => return Err(Error::Http(status)),
+        };
+        // elementsd replies with a non 200 status and a json body for rpc errors
+        let resp: Value = match serde_json::from_str(resp_body) {
+            Ok(v) => v,
+            Err(_) => return Err(Error::Http(status)),
+        };
+
+        if let Some(err) = resp.get("error").filter(|e| !e.is_null()) {
+            return Err(Error::Rpc {
+                code: err.get("code").and_then(Value::as_i64).unwrap_or(0),
+                message: err
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .unwrap_or("")
+                    .to_owned(),
+            });
+        }
+        resp.get("result")
+            .cloned()
+            .ok_or_else(|| Error::UnexpectedResponse(resp.to_string()))
+    }
+
+    /// Call `method` and read the string field `field` from the result
+    fn call_str_field(&self, method: &str, params: &[Value], field: &str) -> Result<String, Error> {
+        let res = self.call(method, params)?;
+        res.get(field)
+            .and_then(Value::as_str)
+            .map(str::to_owned)
+            .ok_or_else(|| Error::UnexpectedResponse(res.to_string()))
+    }
+
+    /// Call `method` with a string result
+    fn call_str(&self, method: &str, params: &[Value]) -> Result<String, Error> {
+        let res = self.call(method, params)?;
+        res.as_str()
+            .map(str::to_owned)
+            .ok_or_else(|| Error::UnexpectedResponse(res.to_string()))
+    }
+
+    /// Fund the raw transaction hex with the wallet at `fee_rate` (in BTC/kvB)
+    pub fn fund_raw_transaction(&self, tx_hex: &str, fee_rate: f64) -> Result<String, Error> {
+        self.call_str_field(
+            "fundrawtransaction",
+            &[json!(tx_hex), json!({ "feeRate": fee_rate })],
+            "hex",
+        )
+    }
+
+    /// Convert a raw transaction hex into a base64 pset
+    pub fn convert_to_psbt(&self, tx_hex: &str) -> Result<String, Error> {
+        self.call_str("converttopsbt", &[json!(tx_hex)])
+    }
+
+    /// Blind and sign all the wallet inputs of the base64 pset
+    pub fn wallet_process_psbt(&self, pset: &str) -> Result<String, Error> {
+        self.call_str_field("walletprocesspsbt", &[json!(pset)], "psbt")
+    }
+
+    /// Broadcast the raw transaction hex, returning the txid
+    pub fn send_raw_transaction(&self, tx_hex: &str) -> Result<elements::Txid, Error> {
+        let txid = self.call_str("sendrawtransaction", &[json!(tx_hex)])?;
+        txid.parse()
+            .map_err(|_| Error::UnexpectedResponse(txid.clone()))
+    }
+}
+
+/// Perform all the steps of the contract operation `ty` against elementsd:
+/// fundrawtransaction, converttopsbt, addcontract, walletprocesspsbt, finalize,
+/// verify and sendrawtransaction at `fee_rate` sat/vB. Returns the txid of the
+/// broadcasted transaction.
+pub fn spend_contract(
+    client: &Client,
+    contract: &OptionContract,
+    ty: SpendType,
+    contract_prevout: OutPoint,
+    addr: Address,
+    fee_rate: f64,
+) -> Result<elements::Txid, contract::Error> {
+    check_wallet_blinding(ty, &addr)?;
+    let tx = contract.partial_tx(ty)?;
+    // The wallet pays the fee for the partial tx, convert sat/vB to BTC/kvB
+    let funded = client.fund_raw_transaction(&serialize_hex(&tx), fee_rate / 100_000.0)?;
+    let funded = client.convert_to_psbt(&funded)?;
+
+    let mut pset = pset_from_base64(&funded)?;
+    contract.add_contract_with_fee(&mut pset, contract_prevout, addr, ty, fee_rate)?;
+
+    let signed = client.wallet_process_psbt(&pset_to_base64(&pset))?;
+    let mut pset = pset_from_base64(&signed)?;
+    contract.finalize_tx(&mut pset, ty)?;
+
+    let tx = pset.extract_tx()?;
+    // Never broadcast a transaction that does not satisfy the covenant
+    if !verify::verify_spend(contract, &tx, ty)?.pass {
+        return Err(contract::Error::UnsatisfiedCovenant);
+    }
+    Ok(client.send_raw_transaction(&serialize_hex(&tx))?)
+}
+
+/// A mock JSON-RPC server for running the rpc pipeline offline in tests
+#[cfg(test)]
+pub mod mock {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use serde_json::{json, Value};
+
+    /// A server answering each request with the result of the handler.
+    /// Every request is recorded as (method, authorization header).
+    pub struct MockServer {
+        pub url: String,
+        pub requests: Arc<Mutex<Vec<(String, Option<String>)>>>,
+    }
+
+    impl MockServer {
+        pub fn start<F>(handler: F) -> MockServer
+        where
+            F: Fn(&str, &[Value]) -> Result<Value, (i64, String)> + Send + 'static,
+        {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let requests = Arc::new(Mutex::new(vec![]));
+            let recorded = requests.clone();
+
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut content_len = 0;
+                    let mut auth = None;
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        let line = line.trim_end();
+                        if line.is_empty() {
+                            break;
+                        }
+                        if line.starts_with("Content-Length: ") {
+                            content_len = line["Content-Length: ".len()..].parse().unwrap();
+                        }
+                        if line.starts_with("Authorization: ") {
+                            auth = Some(line["Authorization: ".len()..].to_owned());
+                        }
+                    }
+                    let mut body = vec![0u8; content_len];
+                    reader.read_exact(&mut body).unwrap();
+                    let req: Value = serde_json::from_slice(&body).unwrap();
+                    let method = req["method"].as_str().unwrap().to_owned();
+                    let params = req["params"].as_array().cloned().unwrap_or_default();
+                    recorded.lock().unwrap().push((method.clone(), auth));
+
+                    let (status, resp) = match handler(&method, &params) {
+                        Ok(res) => (
+                            "200 OK",
+                            json!({"result": res, "error": null, "id": req["id"]}),
+                        ),
+                        Err((code, message)) => (
+                            "500 Internal Server Error",
+                            json!({
+                                "result": null,
+                                "error": {"code": code, "message": message},
+                                "id": req["id"],
+                            }),
+                        ),
+                    };
+                    let resp = resp.to_string();
+                    write!(
+                        stream,
+                        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                        status,
+                        resp.len(),
+                        resp
+                    )
+                    .unwrap();
+                }
+            });
+
+            MockServer { url, requests }
+        }
+
+        /// The methods called on the server so far
+        pub fn methods(&self) -> Vec<String> {
+            self.requests
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|r| r.0.clone())
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockServer;
+    use super::*;
+
+    #[test]
+    fn test_rpc_call() {
+        let server = MockServer::start(|method, params| match method {
+            "getblockcount" => Ok(json!(101)),
+            "sendrawtransaction" => Err((-26, format!("bad-txns {}", params[0]))),
+            _ => Err((-32601, "Method not found".to_owned())),
+        });
+        let client = Client::new(
+            &server.url,
+            Auth::UserPass("user".to_owned(), "pass".to_owned()),
+        )
+        .unwrap();
+
+        assert_eq!(client.call("getblockcount", &[]).unwrap(), json!(101));
+        match client.send_raw_transaction("00") {
+            Err(Error::Rpc { code, .. }) => assert_eq!(code, -26),
+            r => panic!("Expected rpc error, got {:?}", r),
+        }
+        let requests = server.requests.lock().unwrap();
+        assert_eq!(requests[0].1, Some("Basic dXNlcjpwYXNz".to_owned()));
+    }
+
+    #[test]
+    fn test_spend_contract() {
+        use config::BTC_ASSET;
+        use contract::test_contract;
+        use elements::hashes::hex::FromHex;
+        use elements::pset::PartiallySignedTransaction as Pset;
+        use elements::{confidential, AddressParams, AssetId, Script, Transaction, TxOut};
+
+        fn decode_tx(v: &Value) -> Transaction {
+            let bytes = Vec::<u8>::from_hex(v.as_str().unwrap()).unwrap();
+            elements::encode::deserialize(&bytes).unwrap()
+        }
+        let server = MockServer::start(|method, params| match method {
+            // Pretend that the wallet funded the transaction and added the fee output
+            "fundrawtransaction" => {
+                let mut tx = decode_tx(&params[0]);
+                let mut fee = TxOut::default();
+                fee.asset = confidential::Asset::Explicit(AssetId::from_slice(&BTC_ASSET).unwrap());
+                fee.value = confidential::Value::Explicit(1_000);
+                fee.script_pubkey = Script::new();
+                tx.output.push(fee);
+                let hex = serialize_hex(&tx);
+                Ok(json!({"hex": hex, "fee": 0.00001, "changepos": -1}))
+            }
+            "converttopsbt" => Ok(json!(pset_to_base64(&Pset::from_tx(decode_tx(&params[0]))))),
+            "walletprocesspsbt" => Ok(json!({"psbt": params[0], "complete": false})),
+            "sendrawtransaction" => Ok(json!(decode_tx(&params[0]).txid().to_string())),
+            _ => Err((-32601, "Method not found".to_owned())),
+        });
+        let client = Client::new(&server.url, Auth::None).unwrap();
+
+        let contract = test_contract();
+        let addr = contract
+            .deposit_addr(&AddressParams::ELEMENTS)
+            .expect("Valid contract");
+        let prevout = OutPoint::new(elements::Txid::default(), 0);
+        let txid = spend_contract(&client, &contract, SpendType::Exercise, prevout, addr, 1.0)
+            .expect("Pipeline succeeds");
+
+        assert_eq!(
+            server.methods(),
+            vec![
+                "fundrawtransaction",
+                "converttopsbt",
+                "walletprocesspsbt",
+                "sendrawtransaction"
+            ]
+        );
+        assert_ne!(txid, elements::Txid::default());
+    }
+
+    #[test]
+    fn test_invalid_url() {
+        assert!(Client::new("https://127.0.0.1:18884", Auth::None).is_err());
+        assert!(Client::new("http://", Auth::None).is_err());
+        let client = Client::new("http://127.0.0.1:18884/wallet/w1", Auth::None).unwrap();
+        assert_eq!(client.host, "127.0.0.1:18884");
+        assert_eq!(client.path, "/wallet/w1");
+    }
+}
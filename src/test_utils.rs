@@ -0,0 +1,45 @@
+//! Fixtures shared by the tests of the modules of the library
+use bitcoin;
+use elements::{AssetId, Script, Txid};
+use std::str::FromStr;
+
+use config::{Config, BTC_ASSET, CTRL_PK, CTRL_SK};
+use funding::Utxo;
+
+/// A config with fixed assets and tokens, with one token
+pub fn test_config() -> Config {
+    Config {
+        lock_asset: AssetId::from_slice(&BTC_ASSET).unwrap(),
+        claim_asset: AssetId::from_slice(&[1u8; 32]).unwrap(),
+        opt_token: AssetId::from_slice(&[2u8; 32]).unwrap(),
+        bene_token: AssetId::from_slice(&[3u8; 32]).unwrap(),
+        locked_asset_amount: 100_000_000,
+        control_pk: bitcoin::PublicKey::from_str(CTRL_PK).unwrap(),
+        control_sk: Some(bitcoin::PrivateKey::from_wif(CTRL_SK).unwrap()),
+        btc_asset: AssetId::from_slice(&BTC_ASSET).unwrap(),
+        network: Default::default(),
+        claim_asset_precision: 8,
+        opt_token_supply: 1,
+        option_style: Default::default(),
+        exercise_window: 0,
+        oracle_pk: None,
+        settlement_prices: vec![],
+    }
+}
+
+/// A wallet utxo of `value` sats of `asset`, with a p2wpkh script pubkey which
+/// is different for each `vout`
+pub fn test_utxo(vout: u32, asset: AssetId, value: u64) -> Utxo {
+    let mut spk = vec![0x00, 0x14];
+    spk.extend_from_slice(&[vout as u8; 20]);
+    Utxo {
+        txid: Txid::default(),
+        vout,
+        asset,
+        value,
+        asset_blinder: None,
+        value_blinder: None,
+        script_pubkey: Script::from(spk),
+        max_satisfaction_weight: None,
+    }
+}
@@ -0,0 +1,229 @@
+//! Local verification of the transactions spending an option covenant
+//!
+//! The covenant input is checked against the conditions of the covenant branch
+//! of the contract operation before the transaction is broadcast: the witness
+//! script, the `after()` lock time and each txout fixed by `outputs_pref`. The
+//! witness is then evaluated against the covenant script by the miniscript
+//! interpreter, which checks the signature of the control key against the
+//! transaction and, for cash-settled options, the oracle signature checked by
+//! `csfs`. Elementsd rejects the transaction with a generic script error, the
+//! report tells which condition failed.
+use bitcoin::PublicKey;
+use elements::secp256k1_zkp::{Message, Secp256k1, Signature};
+use elements::sighash::SigHashCache;
+use elements::{SigHashType, Transaction, TxOut};
+use miniscript::interpreter::Interpreter;
+use miniscript::ElementsSig;
+use serde::Serialize;
+
+use contract::{Error, OptionContract, SpendType, LOCKTIME_THRESHOLD};
+
+/// The sequence disabling the lock time of the transaction
+const SEQUENCE_FINAL: u32 = 0xffff_ffff;
+
+/// The result of verifying the transaction spending the covenant
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SpendReport {
+    pub spend_type: String,
+    /// The index of the input spending the covenant
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input: Option<usize>,
+    /// The covenant conditions the transaction failed, empty if it passed
+    pub failures: Vec<String>,
+    pub pass: bool,
+}
+
+// Whether the covenant outputs have the same serialization, ignoring the proofs
+// which are not committed to by `outputs_pref`
+fn same_cov_txout(out: &TxOut, expected: &TxOut) -> bool {
+    out.asset == expected.asset
+        && out.value == expected.value
+        && out.nonce == expected.nonce
+        && out.script_pubkey == expected.script_pubkey
+}
+
+// The signature of a witness element signed with the sighash type appended
+pub(crate) fn parse_rawsig(elem: &[u8]) -> Option<(Signature, SigHashType)> {
+    let (&last, der) = elem.split_last()?;
+    let sighash_type = [
+        SigHashType::All,
+        SigHashType::None,
+        SigHashType::Single,
+        SigHashType::AllPlusAnyoneCanPay,
+        SigHashType::NonePlusAnyoneCanPay,
+        SigHashType::SinglePlusAnyoneCanPay,
+    ]
+    .iter()
+    .cloned()
+    .find(|ty| ty.as_u32() == last as u32)?;
+    Signature::from_der(der).ok().map(|sig| (sig, sighash_type))
+}
+
+/// Verifies that the covenant input of the finalized transaction `tx` satisfies
+/// the covenant branch of the contract operation `ty`
+pub fn verify_spend(
+    contract: &OptionContract,
+    tx: &Transaction,
+    ty: SpendType,
+) -> Result<SpendReport, Error> {
+    let (desc, utxo) = contract.spent_cov(ty)?;
+    let witness_script = desc.explicit_script();
+    let mut report = SpendReport {
+        spend_type: ty.to_string(),
+        input: None,
+        failures: vec![],
+        pass: false,
+    };
+    let cov_index = tx.input.iter().position(|txin| {
+        txin.witness.script_witness.last().map(|w| &w[..]) == Some(witness_script.as_bytes())
+    });
+    let cov_index = match cov_index {
+        Some(i) => i,
+        None => {
+            report
+                .failures
+                .push("witness script: no input spends the covenant".to_owned());
+            return Ok(report);
+        }
+    };
+    report.input = Some(cov_index);
+    let witness = &tx.input[cov_index].witness.script_witness;
+
+    // after(): the lock time of the same type must be at or past the required lock time
+    if let Some(locktime) = contract.required_locktime(ty)? {
+        if tx.input[cov_index].sequence == SEQUENCE_FINAL {
+            report.failures.push(format!(
+                "after({}): the input sequence disables the lock time",
+                locktime
+            ));
+        } else if (tx.lock_time < LOCKTIME_THRESHOLD) != (locktime < LOCKTIME_THRESHOLD)
+            || tx.lock_time < locktime
+        {
+            report.failures.push(format!(
+                "after({}): the transaction lock time is {}",
+                locktime, tx.lock_time
+            ));
+        }
+    }
+
+    // outputs_pref(): the first outputs must be the txouts of the branch
+    for (i, (txout, name)) in contract.cov_outputs(ty)?.into_iter().enumerate() {
+        match tx.output.get(i) {
+            Some(out) if same_cov_txout(out, &txout) => {}
+            Some(_) => report.failures.push(format!(
+                "outputs_pref: output {} is not the {} output",
+                i, name
+            )),
+            None => report.failures.push(format!(
+                "outputs_pref: missing the {} output at position {}",
+                name, i
+            )),
+        }
+    }
+
+    // Evaluate the witness against the covenant script. Signatures are checked
+    // against the sighash of the transaction with the covenant script code
+    let txin = &tx.input[cov_index];
+    let interpreter = match Interpreter::from_txdata(
+        &utxo.script_pubkey,
+        &txin.script_sig,
+        witness,
+        txin.sequence,
+        tx.lock_time,
+    ) {
+        Ok(interpreter) => interpreter,
+        Err(e) => {
+            report.failures.push(format!("interpreter: {}", e));
+            return Ok(report);
+        }
+    };
+    let secp = Secp256k1::verification_only();
+    let mut sighash_cache = SigHashCache::new(tx);
+    let script_code = desc.cov_script_code();
+    let verify_sig = |pk: &PublicKey, (sig, sighash_type): ElementsSig| {
+        let sighash =
+            sighash_cache.segwitv0_sighash(cov_index, &script_code, utxo.value, sighash_type);
+        let msg = Message::from_slice(&sighash[..]).expect("32 byte sighash");
+        secp.verify(&msg, &sig, &pk.key).is_ok()
+    };
+    // The interpreter stops at the first constraint the witness does not satisfy
+    if let Some(e) = interpreter
+        .iter(verify_sig)
+        .filter_map(|res| res.err())
+        .next()
+    {
+        report.failures.push(format!("interpreter: {}", e));
+    }
+
+    report.pass = report.failures.is_empty();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::new_private_key;
+    use contract::test_contract;
+    use elements::pset::PartiallySignedTransaction as Pset;
+    use elements::{AddressParams, OutPoint};
+
+    #[test]
+    fn test_verify_spend() {
+        let contract = test_contract();
+        let mut pset = Pset::from_tx(contract.exercise_opt().unwrap());
+        let addr = contract.deposit_addr(&AddressParams::ELEMENTS).unwrap();
+        let prevout = OutPoint::new(elements::Txid::default(), 0);
+        contract
+            .exercise_opt_tx2(&mut pset, prevout, addr)
+            .expect("Valid exercise");
+        contract
+            .finalize_tx(&mut pset, SpendType::Exercise)
+            .expect("Finalized exercise");
+        let tx = pset.extract_tx().unwrap();
+        let report = verify_spend(&contract, &tx, SpendType::Exercise).unwrap();
+        assert!(report.pass, "{:?}", report.failures);
+        assert_eq!(report.input, Some(0));
+
+        // Reordering the outputs after signing breaks outputs_pref and the
+        // signature, which the interpreter rejects
+        let mut tampered = tx.clone();
+        tampered.output.swap(0, 1);
+        let report = verify_spend(&contract, &tampered, SpendType::Exercise).unwrap();
+        assert!(!report.pass);
+        assert_eq!(report.failures.len(), 3);
+        assert_eq!(
+            report.failures[..2],
+            [
+                "outputs_pref: output 0 is not the burn output",
+                "outputs_pref: output 1 is not the exercise output",
+            ]
+        );
+        assert!(report.failures[2].starts_with("interpreter: "));
+
+        // A witness signed by another key does not satisfy the covenant
+        let mut forged = tx.clone();
+        let witness = &mut forged.input[0].witness.script_witness;
+        let sig_pos = witness
+            .iter()
+            .position(|w| parse_rawsig(w).is_some())
+            .expect("Control key signature");
+        let secp = Secp256k1::signing_only();
+        let msg = Message::from_slice(&[1u8; 32]).unwrap();
+        let other_sk = new_private_key();
+        let mut forged_sig = secp.sign(&msg, &other_sk.key).serialize_der().to_vec();
+        forged_sig.push(SigHashType::All.as_u32() as u8);
+        witness[sig_pos] = forged_sig;
+        let report = verify_spend(&contract, &forged, SpendType::Exercise).unwrap();
+        assert!(!report.pass);
+        assert_eq!(report.failures.len(), 1);
+        assert!(report.failures[0].starts_with("interpreter: "));
+
+        // The expiry branch is not satisfied by the exercise transaction
+        let report = verify_spend(&contract, &tx, SpendType::Expiry).unwrap();
+        assert_eq!(report.input, Some(0));
+        assert_eq!(
+            report.failures[0],
+            "after(1622332800): the transaction lock time is 0"
+        );
+    }
+}